@@ -0,0 +1,64 @@
+//! Compare formatting the same document from a YAML and a JSON template, with
+//! `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::borrow::Cow;
+
+/// The same document as a YAML template, and as a JSON template.
+const YAML: &str = "{id: %d, name: %q, active: %b, tags: [%(tags)*q], meta: %(meta)s}";
+const JSON: &str =
+    r#"{"id": %d, "name": %q, "active": %b, "tags": [%(tags)*q], "meta": %(meta)s}"#;
+
+fn args(tags: usize) -> Vec<Cow<'static, str>> {
+    let mut args: Vec<Cow<str>> = vec!["1".into(), "foo".into(), "true".into()];
+    args.extend((0..tags).map(|i| format!("tags=tag{i}").into()));
+    args.push(r#"meta={"a": 1, "b": [true, null]}"#.into());
+    args
+}
+
+fn format(c: &mut Criterion) {
+    let mut group = c.benchmark_group("format");
+    for tags in [1, 100, 10000] {
+        let args = args(tags);
+        for (name, template, json_template) in [
+            ("yaml", YAML, false),
+            ("json", JSON, false),
+            ("json-template", JSON, true),
+        ] {
+            let template = jf::Template::parse(template).unwrap();
+            group.bench_with_input(BenchmarkId::new(name, tags), &args, |b, args| {
+                b.iter(|| {
+                    let options = jf::Options::default()
+                        .no_stdin()
+                        .json_template(json_template);
+                    template.format_with(args.iter().cloned(), options).unwrap()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn format_items(c: &mut Criterion) {
+    let mut group = c.benchmark_group("format_items");
+    let stdin = (0..10000)
+        .map(|i| format!("{{\"id\": {i}}}\0"))
+        .collect::<String>();
+    let template = jf::Template::parse("[%*-s]").unwrap();
+    group.bench_function("format_with", |b| {
+        b.iter(|| {
+            let options = jf::Options::default().stdin(stdin.as_bytes());
+            template.format_with([], options).unwrap()
+        })
+    });
+    group.bench_function("format_to", |b| {
+        b.iter(|| {
+            let options = jf::Options::default().stdin(stdin.as_bytes());
+            template.format_to([], options, std::io::sink()).unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, format, format_items);
+criterion_main!(benches);
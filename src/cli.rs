@@ -1,6 +1,8 @@
 use crate as jf;
+use crate::template::Parsed;
 use crate::VERSION;
 use std::env::Args;
+use std::io::{BufRead, IsTerminal, Write};
 use std::iter::Skip;
 use std::{fs, io};
 
@@ -10,23 +12,192 @@ pub enum Format {
     Json,
     PrettyJson,
     Yaml,
+    Toml,
+}
+
+/// How to split stdin into records in the `--each` mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Each {
+    Lines,
+    Nul,
+    Csv,
+}
+
+impl Each {
+    /// Read the records from the reader, where each record is a list of values.
+    pub fn records<'a, R>(
+        self,
+        reader: R,
+    ) -> Box<dyn Iterator<Item = io::Result<Vec<String>>> + 'a>
+    where
+        R: BufRead + 'a,
+    {
+        match self {
+            Self::Lines => Box::new(reader.lines().map(|l| l.map(|l| vec![l]))),
+            Self::Nul => Box::new(
+                reader
+                    .split(b'\0')
+                    .map(|r| r.map(|r| vec![String::from_utf8_lossy(&r).to_string()])),
+            ),
+            Self::Csv => {
+                let reader = csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .flexible(true)
+                    .from_reader(reader);
+                Box::new(reader.into_records().map(|r| {
+                    r.map(|r| r.iter().map(String::from).collect())
+                        .map_err(io::Error::from)
+                }))
+            }
+        }
+    }
+}
+
+/// How to print the list of placeholders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Listing {
+    Table,
+    Json,
+}
+
+impl Listing {
+    /// Format the placeholders as an aligned table, or as a JSON array.
+    pub fn format(self, placeholders: &[jf::PlaceholderInfo]) -> String {
+        match self {
+            Self::Table => placeholders_table(placeholders),
+            Self::Json => placeholders_json(placeholders),
+        }
+    }
+}
+
+/// How to print the errors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl ErrorFormat {
+    /// Parse the error format, `text` or `json`.
+    pub fn parse(format: &str) -> jf::Result<Self> {
+        match format {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(
+                format!("invalid error format '{format}', use `text` or `json`")
+                    .as_str()
+                    .into(),
+            ),
+        }
+    }
+
+    /// Format the error for stderr, see [`diagnostic`] and [`error_json`].
+    ///
+    /// If the template is invalid, all the errors in it are reported together, one
+    /// JSON object per line for the JSON format.
+    pub fn format(self, error: &jf::Error, template: Option<&str>) -> String {
+        let errors: Vec<jf::Error> = match (error, template) {
+            (jf::Error::Template(_), Some(template)) => jf::Template::validate(template)
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            _ => vec![],
+        };
+        let errors = if errors.is_empty() {
+            vec![error]
+        } else {
+            errors.iter().collect()
+        };
+
+        match self {
+            Self::Text => errors
+                .into_iter()
+                .map(|e| diagnostic(e, template))
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+            Self::Json => errors
+                .into_iter()
+                .map(error_json)
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+/// A file to read the named values from, where `-` is stdin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValuesFile {
+    /// A JSON or YAML mapping of names to values.
+    Yaml(String),
+    /// `KEY=VALUE` lines in the dotenv format.
+    Env(String),
+}
+
+impl ValuesFile {
+    /// Whether the values are read from stdin, i.e. the file is `-`.
+    fn is_stdin(&self) -> bool {
+        matches!(self, Self::Yaml(file) | Self::Env(file) if file == "-")
+    }
 }
 
 #[derive(Debug)]
 pub enum Cli {
     Help,
     Version,
-    Format(Format, Option<String>, Skip<Args>),
+    Check {
+        template: Option<String>,
+    },
+    ListPlaceholders {
+        template: Option<String>,
+        listing: Listing,
+    },
+    Format {
+        format: Format,
+        template: Option<String>,
+        args: Skip<Args>,
+        strict: bool,
+        json_template: bool,
+        exact_numbers: bool,
+        each: Option<Each>,
+        interactive: bool,
+        values: Vec<ValuesFile>,
+        delimiter: jf::Delimiter,
+        stream: bool,
+    },
 }
 
 impl Cli {
     pub fn parse() -> jf::Result<Self> {
+        Self::parse_with(&mut ErrorFormat::default())
+    }
+
+    /// Parse the CLI arguments, setting the error format as soon as it is parsed
+    /// so that the errors from parsing the rest can be printed with it.
+    pub fn parse_with(error_format: &mut ErrorFormat) -> jf::Result<Self> {
         let mut format = Format::Json;
         let mut template: Option<String> = None;
         let mut args = std::env::args().skip(1);
         let mut is_file = false;
+        let mut strict = false;
+        let mut json_template = false;
+        let mut exact_numbers = false;
+        let mut each = None;
+        let mut check = false;
+        let mut interactive = false;
+        let mut listing = None;
+        let mut values = vec![];
+        let mut delimiter = jf::Delimiter::Nul;
+        let mut header = false;
+        let mut stream = false;
+        // The last option that sets how to read stdin, e.g. `--stdin-csv`
+        let mut stdin_option = None;
 
         while let Some(arg) = args.next() {
+            if arg == "-0" || arg == "-n" || arg.starts_with("--stdin-") {
+                stdin_option =
+                    Some(arg.split('=').next().unwrap_or_default().to_string());
+            }
             match arg.as_str() {
                 "-h" | "--help" => return Ok(Self::Help),
                 "-v" | "--version" => return Ok(Self::Version),
@@ -39,9 +210,105 @@ impl Cli {
                 "-y" | "--yaml" => {
                     format = Format::Yaml;
                 }
+                "-t" | "--toml" => {
+                    format = Format::Toml;
+                }
                 "-f" | "--file" => {
                     is_file = true;
                 }
+                "-0" => {
+                    delimiter = jf::Delimiter::Nul;
+                }
+                "-n" => {
+                    delimiter = jf::Delimiter::Newline;
+                }
+                "--stdin-whole" => {
+                    delimiter = jf::Delimiter::Whole;
+                }
+                "--stdin-csv" => {
+                    delimiter = jf::Delimiter::Csv {
+                        delimiter: b',',
+                        header: false,
+                    };
+                }
+                "--stdin-tsv" => {
+                    delimiter = jf::Delimiter::Csv {
+                        delimiter: b'\t',
+                        header: false,
+                    };
+                }
+                "--stdin-header" => {
+                    header = true;
+                }
+                "--stdin-delimiter" => {
+                    let Some(delim) = args.next() else {
+                        return Err("missing delimiter for --stdin-delimiter".into());
+                    };
+                    delimiter = parse_delimiter(&delim)?;
+                }
+                a if a.starts_with("--stdin-delimiter=") => {
+                    delimiter = parse_delimiter(&a["--stdin-delimiter=".len()..])?;
+                }
+                "--strict" => {
+                    strict = true;
+                }
+                "--json-template" => {
+                    json_template = true;
+                }
+                "--exact-numbers" => {
+                    exact_numbers = true;
+                }
+                "--check" => {
+                    check = true;
+                }
+                "--stream" => {
+                    stream = true;
+                }
+                "--interactive" => {
+                    interactive = true;
+                }
+                "--values" => {
+                    let Some(path) = args.next() else {
+                        return Err("missing file for --values".into());
+                    };
+                    values.push(ValuesFile::Yaml(path));
+                }
+                "--env-file" => {
+                    let Some(path) = args.next() else {
+                        return Err("missing file for --env-file".into());
+                    };
+                    values.push(ValuesFile::Env(path));
+                }
+                a if a.starts_with("--values=") => {
+                    values.push(ValuesFile::Yaml(a["--values=".len()..].into()));
+                }
+                a if a.starts_with("--env-file=") => {
+                    values.push(ValuesFile::Env(a["--env-file=".len()..].into()));
+                }
+                "--list-placeholders" | "--list-placeholders=table" => {
+                    listing = Some(Listing::Table);
+                }
+                "--list-placeholders=json" => {
+                    listing = Some(Listing::Json);
+                }
+                "--each" | "--each=lines" => {
+                    each = Some(Each::Lines);
+                }
+                "--each=nul" => {
+                    each = Some(Each::Nul);
+                }
+                "--each=csv" => {
+                    each = Some(Each::Csv);
+                }
+                "--error-format" => {
+                    let Some(format) = args.next() else {
+                        return Err("missing format for --error-format".into());
+                    };
+                    *error_format = ErrorFormat::parse(&format)?;
+                }
+                a if a.starts_with("--error-format=") => {
+                    *error_format = ErrorFormat::parse(&a["--error-format=".len()..])?;
+                }
                 "-" => {
                     is_file = false;
                     template = Some(io::read_to_string(io::stdin().lock())?);
@@ -66,35 +333,597 @@ impl Cli {
             template = args.next()
         }
 
+        if let (Some(_), Some(option)) = (&each, stdin_option) {
+            return Err(format!(
+                "{option} cannot be used with --each, use --each=MODE to split stdin"
+            )
+            .as_str()
+            .into());
+        }
+
+        if header {
+            let jf::Delimiter::Csv { header, .. } = &mut delimiter else {
+                return Err(
+                    "--stdin-header can only be used with --stdin-csv or --stdin-tsv"
+                        .into(),
+                );
+            };
+            *header = true;
+        }
+
         if is_file {
             if let Some(tmpl) = template.as_mut() {
                 *tmpl = fs::read_to_string(&tmpl)?;
             }
         }
 
-        Ok(Self::Format(format, template, args))
+        if let Some(listing) = listing {
+            return Ok(Self::ListPlaceholders { template, listing });
+        }
+
+        if check {
+            return Ok(Self::Check { template });
+        }
+
+        Ok(Self::Format {
+            format,
+            template,
+            args,
+            strict,
+            json_template,
+            exact_numbers,
+            each,
+            interactive,
+            values,
+            delimiter,
+            stream,
+        })
+    }
+
+    /// The template to render, if any.
+    pub fn template(&self) -> Option<&str> {
+        match self {
+            Self::Format { template, .. }
+            | Self::Check { template }
+            | Self::ListPlaceholders { template, .. } => template.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Whether the output should be written as it is rendered, instead of only
+    /// once it is complete. Each record of `--each` is always written as soon as it
+    /// is rendered.
+    pub fn is_streaming(&self) -> bool {
+        matches!(
+            self,
+            Self::Format { stream: true, .. } | Self::Format { each: Some(_), .. }
+        )
+    }
+
+    /// Process the command, returning the output to print, if any.
+    pub fn process(self) -> Result<Option<String>, jf::Error> {
+        let mut output = vec![];
+        self.process_to(&mut output)?;
+        // Strip the new line that ends the output
+        if output.pop().is_none() {
+            return Ok(None);
+        }
+        Ok(Some(String::from_utf8_lossy(&output).into_owned()))
     }
 
-    pub fn process(self) -> Result<String, jf::Error> {
+    /// Process the command, writing the output as it is rendered, followed by a new
+    /// line.
+    pub fn process_to<W>(self, mut writer: W) -> Result<(), jf::Error>
+    where
+        W: Write,
+    {
         match self {
-            Self::Help => Ok(jf::USAGE.into()),
-            Self::Version => Ok(format!("jf {VERSION}")),
-            Self::Format(Format::Raw, template, args) => {
-                jf::render(template.iter().map(Into::into).chain(args.map(Into::into)))
+            Self::Help => writeln!(writer, "{}", jf::USAGE)?,
+            Self::Version => writeln!(writer, "jf {VERSION}")?,
+            Self::Check { template } => {
+                let Some(template) = template else {
+                    return Err("not enough arguments, expected at least one".into());
+                };
+                jf::Template::parse(&template)?.check()?;
             }
-            Self::Format(Format::Json, template, args) => {
-                jf::format(template.iter().map(Into::into).chain(args.map(Into::into)))
+            Self::ListPlaceholders { template, listing } => {
+                let Some(template) = template else {
+                    return Err("not enough arguments, expected at least one".into());
+                };
+                let placeholders = jf::Template::parse(&template)?.placeholders();
+                writeln!(writer, "{}", listing.format(&placeholders))?;
+            }
+            Self::Format {
+                format,
+                template,
+                args,
+                strict,
+                json_template,
+                exact_numbers,
+                each: Some(each),
+                interactive,
+                values,
+                ..
+            } => {
+                if !matches!(format, Format::Raw | Format::Json) {
+                    return Err("--each can only be used with JSON or raw output".into());
+                }
+                if interactive {
+                    return Err("--interactive cannot be used with --each".into());
+                }
+                if values.iter().any(ValuesFile::is_stdin) {
+                    return Err(
+                        "--values - and --env-file - cannot be used with --each".into(),
+                    );
+                }
+                let Some(template) = template else {
+                    return Err("not enough arguments, expected at least one".into());
+                };
+                let template = jf::Template::parse(&template)?;
+                let args: Vec<String> = args.collect();
+                let values = load_values(&values)?;
+
+                for record in each.records(io::stdin().lock()) {
+                    // Values from the record come before the values from the arguments
+                    let args = record?.into_iter().chain(args.iter().cloned());
+                    let args = args.map(Into::into);
+                    let options = jf::Options::default()
+                        .strict(strict)
+                        .json_template(json_template)
+                        .exact_numbers(exact_numbers)
+                        .no_stdin()
+                        .values(&values)?;
+                    match format {
+                        Format::Raw => template.render_to(args, options, &mut writer)?,
+                        _ => template.format_to(args, options, &mut writer)?,
+                    };
+                    writer.write_all(b"\n")?;
+                    writer.flush()?;
+                }
+            }
+            Self::Format {
+                format,
+                template,
+                args,
+                strict,
+                json_template,
+                exact_numbers,
+                each: None,
+                interactive,
+                values,
+                delimiter,
+                ..
+            } => {
+                if let (true, Some(template)) =
+                    (values.iter().any(ValuesFile::is_stdin), &template)
+                {
+                    let placeholders = jf::Template::parse(template)?.placeholders();
+                    if placeholders.iter().any(|placeholder| {
+                        placeholder.input == "stdin"
+                            || placeholder.default_file.as_deref() == Some("-")
+                    }) {
+                        return Err("--values - and --env-file - cannot be used with the placeholders that read stdin".into());
+                    }
+                }
+                let mut options = jf::Options::default()
+                    .strict(strict)
+                    .json_template(json_template)
+                    .exact_numbers(exact_numbers)
+                    .delimiter(delimiter)
+                    .values(&load_values(&values)?)?;
+                if interactive {
+                    if !io::stdin().is_terminal() {
+                        return Err(
+                            "--interactive can only be used when stdin is a terminal"
+                                .into(),
+                        );
+                    }
+                    options = options.prompt(ask);
+                }
+                let args = template.iter().map(Into::into).chain(args.map(Into::into));
+                match format {
+                    Format::Raw => jf::render_to(args, options, &mut writer)?,
+                    Format::Json => jf::format_to(args, options, &mut writer)?,
+                    Format::PrettyJson => {
+                        write!(writer, "{}", jf::format_pretty_with(args, options)?)?
+                    }
+                    Format::Yaml => {
+                        write!(writer, "{}", jf::format_yaml_with(args, options)?)?
+                    }
+                    Format::Toml => {
+                        write!(writer, "{}", jf::format_toml_with(args, options)?)?
+                    }
+                };
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse the stdin delimiter, where `\0`, `\n`, `\r`, `\t` and `\\` are escapes.
+pub fn parse_delimiter(delimiter: &str) -> jf::Result<jf::Delimiter> {
+    let mut bytes = vec![];
+    let mut chars = delimiter.chars();
+    while let Some(ch) = chars.next() {
+        let ch = match ch {
+            '\\' => match chars.next() {
+                Some('0') => '\0',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('\\') => '\\',
+                _ => {
+                    return Err(format!(
+                        "invalid escape in stdin delimiter '{delimiter}', use \\0, \\n, \\r, \\t or \\\\"
+                    )
+                    .as_str()
+                    .into())
+                }
+            },
+            ch => ch,
+        };
+        bytes.extend(ch.encode_utf8(&mut [0; 4]).as_bytes());
+    }
+
+    if bytes.is_empty() {
+        return Err(
+            "empty stdin delimiter, use --stdin-whole to read stdin as one value".into(),
+        );
+    }
+    Ok(jf::Delimiter::Bytes(bytes))
+}
+
+/// Load the named values from the given files. The values in the later files
+/// replace the ones in the earlier files.
+pub fn load_values(
+    files: &[ValuesFile],
+) -> jf::Result<jf::json::Map<String, jf::json::Value>> {
+    let read = |file: &str| -> jf::Result<String> {
+        if file == "-" {
+            Ok(io::read_to_string(io::stdin().lock())?)
+        } else {
+            Ok(fs::read_to_string(file)?)
+        }
+    };
+
+    let mut values = jf::json::Map::new();
+    for file in files {
+        match file {
+            ValuesFile::Yaml(file) => {
+                let text = read(file)?;
+                // Read a JSON file as JSON, to keep the numbers as written with the
+                // `arbitrary_precision` feature, but reject the duplicate keys as in
+                // YAML.
+                let json = jf::json::from_str::<Parsed>(&text)
+                    .and_then(|_| jf::json::from_str(&text));
+                let value = match json {
+                    Ok(value) => value,
+                    Err(_) => {
+                        let value: jf::yaml::Value =
+                            jf::yaml::from_str(&text).map_err(|e| {
+                                jf::Error::Jf(format!(
+                                    "invalid values file '{file}': {e}"
+                                ))
+                            })?;
+                        jf::json::to_value(value)?
+                    }
+                };
+                match value {
+                    jf::json::Value::Null => {}
+                    jf::json::Value::Object(map) => values.extend(map),
+                    _ => {
+                        return Err(
+                            "named values must be a map of names to values".into()
+                        )
+                    }
+                }
+            }
+            ValuesFile::Env(file) => {
+                let vars = parse_dotenv(&read(file)?).map_err(|e| {
+                    jf::Error::Jf(format!("invalid env file '{file}', {e}"))
+                })?;
+                values.extend(vars.into_iter().map(|(k, v)| (k, v.into())));
             }
-            Self::Format(Format::PrettyJson, template, args) => jf::format_pretty(
-                template.iter().map(Into::into).chain(args.map(Into::into)),
-            ),
-            Self::Format(Format::Yaml, template, args) => jf::format_yaml(
-                template.iter().map(Into::into).chain(args.map(Into::into)),
-            ),
         }
     }
+    Ok(values)
 }
 
-pub fn parse_and_process() -> Result<String, jf::Error> {
+/// Parse the `KEY=VALUE` lines of a dotenv file, in order.
+///
+/// Blank lines and lines starting with `#` are skipped, and the keys can have an
+/// `export` prefix. Values can be unquoted, where a ` #` starts a comment,
+/// single quoted to be used as is, or double quoted to support escapes like `\n`.
+/// Quoted values can span multiple lines.
+pub fn parse_dotenv(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars = vec![];
+    let mut lines = text.lines().enumerate().map(|(i, l)| (i + 1, l));
+
+    while let Some((number, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("expected KEY=VALUE at line {number}"));
+        };
+        let key = key.trim_end();
+        if key.is_empty() || !key.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(format!("invalid key '{key}' at line {number}"));
+        }
+
+        let value = value.trim_start();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let (value, rest) = read_quoted(quote, &value[1..], &mut lines)
+                    .ok_or_else(|| format!("unclosed quote at line {number}"))?;
+                let rest = rest.trim_start();
+                if !rest.is_empty() && !rest.starts_with('#') {
+                    return Err(format!("unexpected '{rest}' at line {number}"));
+                }
+                value
+            }
+            _ => {
+                let end = value
+                    .char_indices()
+                    .find(|(i, c)| {
+                        *c == '#' && value[..*i].ends_with(|c: char| c.is_whitespace())
+                    })
+                    .map_or(value.len(), |(i, _)| i);
+                value[..end].trim_end().to_string()
+            }
+        };
+
+        vars.push((key.to_string(), value));
+    }
+
+    Ok(vars)
+}
+
+/// Read a quoted value until the closing quote, continuing to the next lines if
+/// needed. Returns the value and the rest of the line after the closing quote.
+fn read_quoted<'a, L>(
+    quote: char,
+    line: &'a str,
+    lines: &mut L,
+) -> Option<(String, &'a str)>
+where
+    L: Iterator<Item = (usize, &'a str)>,
+{
+    let mut value = String::new();
+    let mut line = line;
+
+    loop {
+        let mut chars = line.char_indices();
+        while let Some((i, ch)) = chars.next() {
+            match ch {
+                ch if ch == quote => return Some((value, &line[i + 1..])),
+                '\\' if quote == '"' => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, 'r')) => value.push('\r'),
+                    Some((_, 't')) => value.push('\t'),
+                    Some((_, ch @ ('"' | '\\' | '$'))) => value.push(ch),
+                    Some((_, ch)) => {
+                        value.push('\\');
+                        value.push(ch);
+                    }
+                    None => value.push('\\'),
+                },
+                ch => value.push(ch),
+            }
+        }
+
+        let (_, next) = lines.next()?;
+        value.push('\n');
+        line = next;
+    }
+}
+
+pub fn parse_and_process() -> Result<Option<String>, jf::Error> {
     Cli::parse()?.process()
 }
+
+/// Prompt for the value of a named placeholder on stderr, and read it from a line
+/// of stdin:
+///
+/// ```text
+/// port [d, default: 8080]:
+/// ```
+///
+/// An empty answer uses the default value, null or blank, if the placeholder has
+/// any, else it is used as an empty value.
+pub fn ask(
+    placeholder: &jf::PlaceholderInfo,
+    error: Option<&jf::Error>,
+) -> io::Result<Option<String>> {
+    let default = match placeholder {
+        jf::PlaceholderInfo {
+            default: Some(value),
+            ..
+        } => Some(value.clone()),
+        jf::PlaceholderInfo {
+            default_file: Some(file),
+            ..
+        } => Some(format!("@{file}")),
+        jf::PlaceholderInfo { nullable: true, .. } => Some("null".into()),
+        jf::PlaceholderInfo { optional: true, .. } => Some("blank".into()),
+        _ => None,
+    };
+
+    let mut stderr = io::stderr().lock();
+    if let Some(error) = error {
+        writeln!(stderr, "error: {error}")?;
+    }
+    let name = placeholder.name.as_deref().unwrap_or_default();
+    let specifier = placeholder.specifier;
+    match &default {
+        Some(default) => write!(stderr, "{name} [{specifier}, default: {default}]: ")?,
+        None => write!(stderr, "{name} [{specifier}]: ")?,
+    }
+    stderr.flush()?;
+
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line)? == 0 {
+        writeln!(stderr)?;
+        return Ok(None);
+    }
+    let line = line.strip_suffix('\n').unwrap_or(&line);
+    let line = line.strip_suffix('\r').unwrap_or(line);
+
+    if line.is_empty() && default.is_some() {
+        Ok(None)
+    } else {
+        Ok(Some(line.into()))
+    }
+}
+
+/// Format the error for the terminal, and if the error was caused by a part of
+/// the template, point to it like `rustc` does:
+///
+/// ```text
+/// error: jf: no value for placeholder '%(foo)q' at column 8
+///  --> template:1:9
+///   |
+/// 1 | {foo: %(foo)q}
+///   |       ^^^^^^^
+/// ```
+pub fn diagnostic(error: &jf::Error, template: Option<&str>) -> String {
+    let message = format!("error: {error}");
+    let (Some(span), Some(template)) = (error.span(), template) else {
+        return message;
+    };
+    let Some(line) = template.split('\n').nth(span.line - 1) else {
+        return message;
+    };
+    let line = line.strip_suffix('\r').unwrap_or(line);
+
+    // Underline the part of the span that is on the reported line
+    let line_start = span.offset + 1 - span.column;
+    let line_end = line_start + line.chars().count();
+    let from = span.chars.start.clamp(line_start, line_end) - line_start;
+    let to = span.chars.end.clamp(line_start, line_end) - line_start;
+    let carets = "^".repeat(to.saturating_sub(from).max(1));
+
+    let number = span.line.to_string();
+    let pad = " ".repeat(number.len());
+    let indent = " ".repeat(from);
+    format!(
+        "{message}\n{pad}--> template:{}:{}\n{pad} |\n{number} | {line}\n{pad} | {indent}{carets}",
+        span.line, span.column
+    )
+}
+
+/// Format the error as a single line JSON object for the tools that wrap `jf`:
+///
+/// ```text
+/// {"column":13,"kind":"template","line":1,"message":"jf: no value for placeholder '%(foo)q' at column 12","offset":12,"placeholder":"%(foo)q","returncode":1}
+/// ```
+///
+/// The `line` and `column` fields start from 1, while the `offset` field is the char
+/// offset in the whole template, starting from 0, which is what the message calls
+/// the column. The `line`, `column`, `offset` and `placeholder` fields are `null`
+/// if not known.
+pub fn error_json(error: &jf::Error) -> String {
+    let span = error.span();
+    jf::json::json!({
+        "kind": error.kind(),
+        "message": error.to_string(),
+        "returncode": error.returncode(),
+        "line": span.map(|s| s.line),
+        "column": span.map(|s| s.column),
+        "offset": span.map(|s| s.offset),
+        "placeholder": error.placeholder(),
+    })
+    .to_string()
+}
+
+/// Format the placeholders as a table with a header, one placeholder per row:
+///
+/// ```text
+/// PLACEHOLDER  INPUT  INDEX/NAME  SPECIFIER  EXPANSION  DEFAULT  NULLABLE  OPTIONAL
+/// %s           args   1           s          -          -        no        no
+/// %(foo=1)q    args   foo         q          -          1        no        no
+/// ```
+///
+/// Default values read from files are shown as `@FILE`.
+pub fn placeholders_table(placeholders: &[jf::PlaceholderInfo]) -> String {
+    let yes_no = |b: bool| if b { "yes" } else { "no" }.to_string();
+    let or_dash = |s: &str| if s.is_empty() { "-" } else { s }.to_string();
+
+    let header = [
+        "PLACEHOLDER",
+        "INPUT",
+        "INDEX/NAME",
+        "SPECIFIER",
+        "EXPANSION",
+        "DEFAULT",
+        "NULLABLE",
+        "OPTIONAL",
+    ];
+    let mut rows = vec![header.map(String::from)];
+    for p in placeholders {
+        let key = match (&p.name, p.index) {
+            (Some(name), _) => name.clone(),
+            (None, Some(index)) => index.to_string(),
+            (None, None) => "-".into(),
+        };
+        let default = match (&p.default, &p.default_file) {
+            (Some(value), _) => value.clone(),
+            (None, Some(file)) => format!("@{file}"),
+            (None, None) => "-".into(),
+        };
+        rows.push([
+            p.span.text.clone(),
+            p.input.into(),
+            key,
+            p.specifier.into(),
+            or_dash(p.expansion),
+            default,
+            yes_no(p.nullable),
+            yes_no(p.optional),
+        ]);
+    }
+
+    let widths: Vec<usize> = (0..header.len())
+        .map(|i| rows.iter().map(|r| r[i].chars().count()).max().unwrap_or(0))
+        .collect();
+    rows.iter()
+        .map(|row| {
+            let cells: Vec<_> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect();
+            cells.join("  ").trim_end().to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Format the placeholders as a JSON array of objects. The missing fields are
+/// `null`, e.g. the `index` of a named placeholder.
+pub fn placeholders_json(placeholders: &[jf::PlaceholderInfo]) -> String {
+    let placeholders: Vec<_> = placeholders
+        .iter()
+        .map(|p| {
+            jf::json::json!({
+                "placeholder": p.span.text,
+                "line": p.span.line,
+                "column": p.span.column,
+                "input": p.input,
+                "index": p.index,
+                "name": p.name,
+                "specifier": p.specifier.to_string(),
+                "expansion": Some(p.expansion).filter(|e| !e.is_empty()),
+                "default": p.default,
+                "default_file": p.default_file,
+                "nullable": p.nullable,
+                "optional": p.optional,
+            })
+        })
+        .collect();
+    jf::json::Value::from(placeholders).to_string()
+}
@@ -1,30 +1,346 @@
+use crate::template::Specifier;
 pub use serde_json as json;
 pub use serde_yaml as yaml;
 use std::fmt::Display;
 use std::io;
+use std::ops::Range;
+
+/// The location of a placeholder in the template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    /// The placeholder as written in the template, e.g. `%(foo=bar)q`.
+    pub text: String,
+    /// The char offsets of the placeholder in the template.
+    pub chars: Range<usize>,
+    /// The byte offsets of the placeholder in the template.
+    pub bytes: Range<usize>,
+    /// The char offset that the error points to, reported as the column.
+    pub offset: usize,
+    /// The line number of the offset, starting from 1.
+    pub line: usize,
+    /// The column number of the offset in its line, starting from 1.
+    pub column: usize,
+}
+
+impl Span {
+    pub(crate) fn new(template: &str, chars: Range<usize>, offset: usize) -> Self {
+        let byte = |i| {
+            template
+                .char_indices()
+                .nth(i)
+                .map_or(template.len(), |(b, _)| b)
+        };
+        let bytes = byte(chars.start)..byte(chars.end);
+        let before = &template[..byte(offset)];
+        let line = before.matches('\n').count() + 1;
+        let column = before
+            .rsplit('\n')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .count()
+            + 1;
+
+        Self {
+            text: template[bytes.clone()].to_string(),
+            chars,
+            bytes,
+            offset,
+            line,
+            column,
+        }
+    }
+}
+
+/// An error caused by an invalid template or invalid values for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    /// A positional placeholder with an unknown specifier, e.g. `%z`.
+    InvalidPlaceholder { placeholder: String, span: Span },
+    /// A named placeholder with an unknown specifier, e.g. `%(foo)z`.
+    InvalidNamedPlaceholder { placeholder: String, span: Span },
+    /// The template ended in the middle of a placeholder.
+    IncompletePlaceholder { span: Span },
+    /// A positional placeholder after the named placeholders.
+    PositionalAfterNamed { specifier: char, span: Span },
+    /// A named placeholder without a name, e.g. `%()s`.
+    MissingName { span: Span },
+    /// A character that is not allowed in placeholder names.
+    InvalidCharacter { character: char, span: Span },
+    /// A nested name that is not valid, e.g. `%(foo..bar)s`.
+    InvalidName { name: String, span: Span },
+    /// An environment variable placeholder with a `$` inside the name.
+    MisplacedEnvMarker { name: String, span: Span },
+    /// An environment variable placeholder with a nested name.
+    NestedEnvName { name: String, span: Span },
+    /// An environment variable placeholder with an expansion.
+    ExpandedEnv { name: String, span: Span },
+    /// An optional placeholder with a default value.
+    OptionalWithDefault { name: String, span: Span },
+    /// An optional placeholder that is also nullable.
+    OptionalAndNullable { name: String, span: Span },
+    /// A nullable placeholder that does not end with `?)`.
+    UnclosedNullable { name: String, span: Span },
+    /// An expandable placeholder with a default value.
+    ExpansionWithDefault { name: String, span: Span },
+    /// No positional or stdin value for the placeholder.
+    MissingValue { span: Span },
+    /// No named value or default value for the placeholder.
+    MissingNamedValue { placeholder: String, span: Span },
+    /// A value that is not valid for the placeholder specifier.
+    InvalidValue {
+        value: String,
+        specifier: char,
+        expected: String,
+        span: Span,
+    },
+    /// More positional values than the positional placeholders.
+    TooManyValues,
+    /// A named value not using the `NAME=VALUE` or `NAME@FILE` syntax.
+    InvalidValueSyntax { value_no: usize },
+    /// A nested named value that conflicts with the other named values.
+    ConflictingName { name: String, value_no: usize },
+    /// A nested named value with an array index that is too large.
+    IndexTooLarge {
+        name: String,
+        value_no: usize,
+        max: usize,
+    },
+}
+
+impl TemplateError {
+    /// The location of the placeholder that caused the error, if any.
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Self::InvalidPlaceholder { span, .. }
+            | Self::InvalidNamedPlaceholder { span, .. }
+            | Self::IncompletePlaceholder { span }
+            | Self::PositionalAfterNamed { span, .. }
+            | Self::MissingName { span }
+            | Self::InvalidCharacter { span, .. }
+            | Self::InvalidName { span, .. }
+            | Self::MisplacedEnvMarker { span, .. }
+            | Self::NestedEnvName { span, .. }
+            | Self::ExpandedEnv { span, .. }
+            | Self::OptionalWithDefault { span, .. }
+            | Self::OptionalAndNullable { span, .. }
+            | Self::UnclosedNullable { span, .. }
+            | Self::ExpansionWithDefault { span, .. }
+            | Self::MissingValue { span }
+            | Self::MissingNamedValue { span, .. }
+            | Self::InvalidValue { span, .. } => Some(span),
+            Self::TooManyValues
+            | Self::InvalidValueSyntax { .. }
+            | Self::ConflictingName { .. }
+            | Self::IndexTooLarge { .. } => None,
+        }
+    }
+}
+
+/// Something like "%(foo)*" for "%(foo)*q".
+fn without_specifier(placeholder: &str) -> &str {
+    let mut chars = placeholder.chars();
+    chars.next_back();
+    chars.as_str()
+}
+
+impl Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let col = self.span().map(|s| s.offset).unwrap_or_default();
+        match self {
+            Self::InvalidPlaceholder { placeholder, .. } => {
+                let specifiers = Specifier::one_of(without_specifier(placeholder));
+                write!(f, "invalid placeholder '{placeholder}' at column {col}, use one of {specifiers}, or escape it using '%%'")
+            }
+            Self::InvalidNamedPlaceholder { placeholder, .. } => {
+                let specifiers = Specifier::usage(without_specifier(placeholder));
+                write!(f, "invalid named placeholder '{placeholder}' at column {col}, use {specifiers}")
+            }
+            Self::IncompletePlaceholder { .. } => {
+                write!(f, "template ended with incomplete placeholder")
+            }
+            Self::PositionalAfterNamed { specifier: ch, .. } => {
+                write!(f, "positional placeholder '%{ch}' at column {col} was used after named placeholders, use named placeholder syntax '%(NAME){ch}' instead")
+            }
+            Self::MissingName { .. } => {
+                write!(f, "placeholder missing name at column {col}")
+            }
+            Self::InvalidCharacter { character, .. } => {
+                write!(f, "invalid character {character:?} in placeholder name at column {col}, use numbers, letters and underscores only")
+            }
+            Self::InvalidName { name, .. } => {
+                write!(f, "invalid placeholder name '{name}' at column {col}, use names like 'foo', 'foo.bar' or 'foo[0]'")
+            }
+            Self::MisplacedEnvMarker { name, .. } => {
+                write!(f, "environment variable placeholder '{name}' at column {col} must have '$' either before or after the name")
+            }
+            Self::NestedEnvName { name, .. } => {
+                write!(f, "environment variable placeholder '{name}' at column {col} cannot have a nested name")
+            }
+            Self::ExpandedEnv { name, .. } => {
+                write!(f, "environment variable placeholder '{name}' at column {col} cannot be expanded")
+            }
+            Self::OptionalWithDefault { name, .. } => {
+                write!(f, "optional placeholder '{name}' at column {col} cannot have a default value")
+            }
+            Self::OptionalAndNullable { name, .. } => {
+                write!(f, "optional placeholder '{name}' at column {col} cannot also be nullable")
+            }
+            Self::UnclosedNullable { name, .. } => {
+                write!(
+                    f,
+                    "nullable placeholder '{name}' at column {col} must end with '?)'"
+                )
+            }
+            Self::ExpansionWithDefault { name, .. } => {
+                write!(f, "expandable placeholder '{name}' at column {col} cannot have a default value")
+            }
+            Self::MissingValue { .. } => {
+                write!(f, "placeholder missing value at column {col}")
+            }
+            Self::MissingNamedValue { placeholder, .. } => {
+                write!(
+                    f,
+                    "no value for placeholder '{placeholder}' at column {col}"
+                )
+            }
+            Self::InvalidValue {
+                value,
+                specifier: ch,
+                expected,
+                ..
+            } => {
+                write!(f, "invalid value {value:?} for placeholder '%{ch}' at column {col}, expected {expected}")
+            }
+            Self::TooManyValues => {
+                write!(
+                    f,
+                    "too many positional values, not enough positional placeholders"
+                )
+            }
+            Self::InvalidValueSyntax { value_no } => {
+                write!(f, "invalid syntax for value no. {value_no}, use 'NAME=VALUE' or 'NAME@FILE' syntax")
+            }
+            Self::ConflictingName { name, value_no } => {
+                write!(f, "invalid name '{name}' for value no. {value_no}, it conflicts with the other values")
+            }
+            Self::IndexTooLarge {
+                name,
+                value_no,
+                max,
+            } => {
+                write!(f, "invalid name '{name}' for value no. {value_no}, the index must not be larger than {max}")
+            }
+        }
+    }
+}
+
+/// An error from parsing the rendered template as YAML.
+#[derive(Debug)]
+pub struct YamlError {
+    /// The underlying YAML error.
+    pub error: yaml::Error,
+    /// The location in the template that produced the invalid YAML, if known.
+    pub span: Option<Span>,
+    /// The substituted value that produced the invalid YAML, if known.
+    pub value: Option<ValueSource>,
+}
+
+impl Display for YamlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.value, &self.span) {
+            (Some(value), Some(span)) => write!(
+                f,
+                "{value} substituted at '{}' (column {}) produced invalid YAML: {}",
+                span.text, span.offset, self.error
+            ),
+            _ => write!(f, "{}", self.error),
+        }
+    }
+}
+
+/// Where a substituted value came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueSource {
+    /// The value no. N passed after the template.
+    Arg(usize),
+    /// The value no. N read from stdin.
+    Stdin(usize),
+    /// A default, a file or an environment variable.
+    Other,
+}
+
+impl Display for ValueSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Arg(n) => write!(f, "value no. {n}"),
+            Self::Stdin(n) => write!(f, "stdin value no. {n}"),
+            Self::Other => write!(f, "value"),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum Error {
     Json(json::Error),
-    Yaml(yaml::Error),
+    Yaml(Box<YamlError>),
     Jf(String),
     Io(io::Error),
+    Toml(toml::ser::Error),
+    Template(Box<TemplateError>),
 }
 
 impl Error {
+    /// The location in the template that caused the error, if known.
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Self::Template(e) => e.span(),
+            Self::Yaml(e) => e.span.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// A short name for the kind of the error, e.g. `template` or `yaml`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Json(_) => "json",
+            Self::Yaml(_) => "yaml",
+            Self::Jf(_) => "jf",
+            Self::Io(_) => "io",
+            Self::Toml(_) => "toml",
+            Self::Template(_) => "template",
+        }
+    }
+
+    /// The placeholder that caused the error, as written in the template, if known.
+    pub fn placeholder(&self) -> Option<&str> {
+        match self {
+            Self::Template(e) => e.span().map(|s| s.text.as_str()),
+            Self::Yaml(e) if e.value.is_some() => {
+                e.span.as_ref().map(|s| s.text.as_str())
+            }
+            _ => None,
+        }
+    }
+
     pub fn returncode(&self) -> i32 {
         match self {
-            Self::Jf(_) => 1,
+            Self::Jf(_) | Self::Template(_) => 1,
             Self::Json(_) => 2,
             Self::Yaml(_) => 3,
             Self::Io(_) => 4,
+            Self::Toml(_) => 5,
         }
     }
 }
 
 impl From<yaml::Error> for Error {
     fn from(v: yaml::Error) -> Self {
-        Self::Yaml(v)
+        Self::Yaml(Box::new(YamlError {
+            error: v,
+            span: None,
+            value: None,
+        }))
     }
 }
 
@@ -34,6 +350,18 @@ impl From<json::Error> for Error {
     }
 }
 
+impl From<TemplateError> for Error {
+    fn from(v: TemplateError) -> Self {
+        Self::Template(Box::new(v))
+    }
+}
+
+impl From<toml::ser::Error> for Error {
+    fn from(v: toml::ser::Error) -> Self {
+        Self::Toml(v)
+    }
+}
+
 impl From<&str> for Error {
     fn from(v: &str) -> Self {
         Self::Jf(v.to_string())
@@ -52,7 +380,9 @@ impl Display for Error {
             Self::Json(e) => write!(f, "json: {e}"),
             Self::Yaml(e) => write!(f, "yaml: {e}"),
             Self::Jf(e) => write!(f, "jf: {e}"),
+            Self::Template(e) => write!(f, "jf: {e}"),
             Self::Io(e) => write!(f, "io: {e}"),
+            Self::Toml(e) => write!(f, "toml: {e}"),
         }
     }
 }
@@ -1,9 +1,36 @@
+use jf::cli::{Cli, ErrorFormat};
+use std::io::{self, Write};
+
 fn main() {
-    match jf::cli::parse_and_process() {
-        Ok(v) => println!("{v}"),
-        Err(e) => {
-            eprintln!("error: {e}");
-            std::process::exit(e.returncode());
+    let mut error_format = ErrorFormat::default();
+    let (result, template) = match Cli::parse_with(&mut error_format) {
+        Ok(cli) => {
+            let template = cli.template().map(String::from);
+            let result = if cli.is_streaming() {
+                // Write the output as it is rendered, e.g. the items of a large
+                // array, but drop what is still buffered on error.
+                let mut stdout = io::BufWriter::new(io::stdout().lock());
+                match cli.process_to(&mut stdout) {
+                    Ok(()) => stdout.flush().map_err(Into::into),
+                    Err(e) => {
+                        let _ = stdout.into_parts();
+                        Err(e)
+                    }
+                }
+            } else {
+                // Write the output once it is complete, so that nothing is printed
+                // on error.
+                let mut output = vec![];
+                cli.process_to(&mut output)
+                    .and_then(|()| Ok(io::stdout().lock().write_all(&output)?))
+            };
+            (result, template)
         }
+        Err(e) => (Err(e), None),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", error_format.format(&e, template.as_deref()));
+        std::process::exit(e.returncode());
     }
 }
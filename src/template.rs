@@ -0,0 +1,2663 @@
+use crate::{json, yaml, Error, Result, Span, TemplateError, ValueSource, YamlError};
+use serde::de::{
+    self, Deserialize, DeserializeSeed, Deserializer, EnumAccess, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde::ser::Error as _;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+use std::ops::Range;
+use std::{fmt, fs, io};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expansion {
+    None,
+    Items,
+    Pairs,
+}
+
+impl Expansion {
+    fn stars(&self) -> &'static str {
+        match self {
+            Expansion::None => "",
+            Expansion::Items => "*",
+            Expansion::Pairs => "**",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Specifier {
+    Raw,
+    Quoted,
+    Integer,
+    Float,
+    Boolean,
+    Json,
+}
+
+impl Specifier {
+    const ALL: [Self; 6] = [
+        Self::Raw,
+        Self::Quoted,
+        Self::Integer,
+        Self::Float,
+        Self::Boolean,
+        Self::Json,
+    ];
+
+    fn from_char(ch: char) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.as_char() == ch)
+    }
+
+    fn as_char(&self) -> char {
+        match self {
+            Self::Raw => 's',
+            Self::Quoted => 'q',
+            Self::Integer => 'd',
+            Self::Float => 'f',
+            Self::Boolean => 'b',
+            Self::Json => 'j',
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Self::Raw => "other values",
+            Self::Quoted => "quoted strings",
+            Self::Integer => "integers",
+            Self::Float => "numbers",
+            Self::Boolean => "booleans",
+            Self::Json => "JSON values",
+        }
+    }
+
+    /// A value that passes the validation, used to check the template without
+    /// the real values.
+    fn dummy(&self) -> &'static str {
+        match self {
+            Self::Raw | Self::Integer | Self::Float => "0",
+            Self::Quoted => "x",
+            Self::Boolean => "false",
+            Self::Json => "null",
+        }
+    }
+
+    /// Something like "'%s', '%q', ... or '%j'".
+    pub(crate) fn one_of(prefix: &str) -> String {
+        let all = Self::ALL.map(|s| format!("'{prefix}{}'", s.as_char()));
+        let (rest, last) = all.split_at(all.len() - 1);
+        format!("{} or {}", rest.join(", "), last.join(""))
+    }
+
+    /// Something like "'%(x)q' for quoted strings, ... and '%(x)s' for other values".
+    pub(crate) fn usage(prefix: &str) -> String {
+        let all = Self::ALL
+            .iter()
+            .filter(|s| **s != Self::Raw)
+            .map(|s| format!("'{prefix}{}' for {}", s.as_char(), s.describe()))
+            .collect::<Vec<_>>();
+        let raw = Self::Raw;
+        format!(
+            "{} and '{prefix}{}' for {}",
+            all.join(", "),
+            raw.as_char(),
+            raw.describe()
+        )
+    }
+
+    fn push(
+        &self,
+        val: &mut String,
+        arg: &str,
+        span: &Span,
+        strict: bool,
+        quote: Option<char>,
+    ) -> Result<()> {
+        let trimmed = arg.trim();
+        let validated: std::result::Result<Cow<str>, &str> = match self {
+            Self::Raw if strict => match quote {
+                Some(quote) if !stays_quoted(arg, quote) => {
+                    Err("a value that stays inside the quotes")
+                }
+                None if !is_single_yaml_node(arg) => Err("a single YAML value"),
+                _ => Ok(arg.into()),
+            },
+            Self::Raw => Ok(arg.into()),
+            Self::Quoted => Ok(json::to_string(arg)?.into()),
+            Self::Integer => trimmed
+                .parse::<i64>()
+                .map(|n| n.to_string())
+                .or_else(|_| trimmed.parse::<u64>().map(|n| n.to_string()))
+                .map(Into::into)
+                .map_err(|_| "an integer"),
+            Self::Float => match trimmed.parse::<f64>() {
+                // Keep a JSON number as written, else write it as one, e.g. `.5`
+                // as `0.5`.
+                Ok(f) if f.is_finite() => {
+                    match json::from_str::<json::Number>(trimmed) {
+                        Ok(_) => Ok(trimmed.into()),
+                        Err(_) => Ok(json::Value::from(f).to_string().into()),
+                    }
+                }
+                _ => Err("a finite number"),
+            },
+            Self::Boolean => ["true", "false"]
+                .into_iter()
+                .find(|b| trimmed.eq_ignore_ascii_case(b))
+                .map(Into::into)
+                .ok_or("a boolean"),
+            // A YAML value keeps the keys in order and rejects the duplicate keys
+            Self::Json => json::from_str::<Parsed>(arg)
+                .map(|_| minify_json(arg).into())
+                .map_err(|_| "a single JSON value"),
+        };
+
+        match validated {
+            Ok(value) => {
+                val.push_str(&value);
+                Ok(())
+            }
+            Err(expected) => Err(TemplateError::InvalidValue {
+                value: arg.into(),
+                specifier: self.as_char(),
+                expected: expected.into(),
+                span: span.clone(),
+            }
+            .into()),
+        }
+    }
+}
+
+/// Remove the whitespace between the tokens of a valid JSON value, keeping the
+/// keys, numbers and strings as written.
+fn minify_json(value: &str) -> String {
+    let mut minified = String::with_capacity(value.len());
+    let mut in_string = false;
+    let mut is_escaped = false;
+
+    for ch in value.chars() {
+        if in_string {
+            match ch {
+                _ if is_escaped => is_escaped = false,
+                '\\' => is_escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if ch == '"' {
+            in_string = true;
+        } else if ch.is_ascii_whitespace() {
+            continue;
+        }
+        minified.push(ch);
+    }
+    minified
+}
+
+/// Check that the value cannot close the quotes around it, e.g. `", b: "` for
+/// `"%s"`, or escape the closing quote, e.g. `\` for `"%s"`. Escaped quotes,
+/// e.g. `\"` for `"%s"` or `''` for `'%s'`, stay inside.
+fn stays_quoted(value: &str, quote: char) -> bool {
+    match quote {
+        '"' => {
+            let mut is_escaped = false;
+            for ch in value.chars() {
+                match ch {
+                    '"' if !is_escaped => return false,
+                    '\\' => is_escaped = !is_escaped,
+                    _ => is_escaped = false,
+                }
+            }
+            !is_escaped
+        }
+        // Two single quotes are an escaped single quote
+        _ => !value.replace("''", "").contains('\''),
+    }
+}
+
+/// Mark the placeholders inside quoted scalars with their quote, so that strict
+/// mode can check that the values stay inside the quotes.
+///
+/// A quote only starts a quoted scalar at the start of a value, e.g. not in
+/// `it's`, but a plain scalar with quotes inside, e.g. `a "%s"`, is treated as
+/// quoted too, which is only stricter.
+fn mark_quoted(parts: &mut [Part]) {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum State {
+        Plain,
+        Comment,
+        Double,
+        DoubleEscape,
+        Single,
+        /// A single quote, that is either closing or escaping the next one.
+        SingleEnd,
+    }
+
+    let mut state = State::Plain;
+    let mut prev: Option<char> = None;
+
+    for part in parts.iter_mut() {
+        let placeholder = match part {
+            Part::Literal(literal, _) => {
+                for ch in literal.chars() {
+                    if state == State::SingleEnd {
+                        if ch == '\'' {
+                            state = State::Single;
+                            continue;
+                        }
+                        state = State::Plain;
+                        prev = Some('\'');
+                    }
+
+                    state = match state {
+                        State::Plain => {
+                            let is_start = prev
+                                .is_none_or(|p| p.is_whitespace() || "[{,:".contains(p));
+                            let is_comment = prev.is_none_or(char::is_whitespace);
+                            prev = Some(ch);
+                            match ch {
+                                '"' if is_start => State::Double,
+                                '\'' if is_start => State::Single,
+                                '#' if is_comment => State::Comment,
+                                _ => State::Plain,
+                            }
+                        }
+                        State::Comment if ch == '\n' => {
+                            prev = Some(ch);
+                            State::Plain
+                        }
+                        State::Double if ch == '\\' => State::DoubleEscape,
+                        State::DoubleEscape => State::Double,
+                        State::Double if ch == '"' => {
+                            prev = Some(ch);
+                            State::Plain
+                        }
+                        State::Single if ch == '\'' => State::SingleEnd,
+                        state => state,
+                    };
+                }
+                continue;
+            }
+            Part::Placeholder(placeholder) => placeholder,
+        };
+
+        placeholder.quote = match state {
+            State::Plain | State::Comment | State::SingleEnd => {
+                state = match state {
+                    State::Comment => State::Comment,
+                    _ => State::Plain,
+                };
+                prev = Some('%');
+                None
+            }
+            State::Double | State::DoubleEscape => {
+                state = State::Double;
+                Some('"')
+            }
+            State::Single => Some('\''),
+        };
+    }
+}
+
+/// Check if the value parses as exactly one YAML node, so that substituting it
+/// cannot change the structure of the document around it.
+fn is_single_yaml_node(value: &str) -> bool {
+    if value.contains(['\n', '\r']) {
+        return false;
+    }
+
+    let Ok(yaml::Value::Sequence(nodes)) = yaml::from_str(&format!("[{value}]")) else {
+        return false;
+    };
+
+    match nodes.as_slice() {
+        // Inside brackets, a "key: value" fragment becomes a single pair mapping.
+        [yaml::Value::Mapping(_)] => {
+            let value = value.trim();
+            value.starts_with('{') && value.ends_with('}')
+        }
+        [_] => true,
+        _ => false,
+    }
+}
+
+/// A YAML value parsed from JSON or YAML text.
+///
+/// Like `yaml::Value`, it keeps the keys in order and rejects the duplicate keys.
+/// Unlike it, it also accepts the integers out of the 64-bit range, as floats, and
+/// the numbers of `serde_json` with the `arbitrary_precision` feature.
+pub(crate) struct Parsed(yaml::Value);
+
+impl<'de> Deserialize<'de> for Parsed {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(ParsedVisitor).map(Parsed)
+    }
+}
+
+struct ParsedVisitor;
+
+impl<'de> Visitor<'de> for ParsedVisitor {
+    type Value = yaml::Value;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("any YAML value")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> std::result::Result<Self::Value, E> {
+        Ok(b.into())
+    }
+
+    fn visit_i64<E>(self, i: i64) -> std::result::Result<Self::Value, E> {
+        Ok(i.into())
+    }
+
+    fn visit_u64<E>(self, u: u64) -> std::result::Result<Self::Value, E> {
+        Ok(u.into())
+    }
+
+    fn visit_i128<E>(self, i: i128) -> std::result::Result<Self::Value, E> {
+        Ok((i as f64).into())
+    }
+
+    fn visit_u128<E>(self, u: u128) -> std::result::Result<Self::Value, E> {
+        Ok((u as f64).into())
+    }
+
+    fn visit_f64<E>(self, f: f64) -> std::result::Result<Self::Value, E> {
+        Ok(f.into())
+    }
+
+    fn visit_str<E>(self, s: &str) -> std::result::Result<Self::Value, E> {
+        Ok(s.into())
+    }
+
+    fn visit_string<E>(self, s: String) -> std::result::Result<Self::Value, E> {
+        Ok(s.into())
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<Self::Value, E> {
+        Ok(yaml::Value::Null)
+    }
+
+    fn visit_none<E>(self) -> std::result::Result<Self::Value, E> {
+        Ok(yaml::Value::Null)
+    }
+
+    fn visit_some<D>(self, deserializer: D) -> std::result::Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Parsed::deserialize(deserializer).map(|Parsed(value)| value)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut items = vec![];
+        while let Some(Parsed(item)) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(yaml::Value::Sequence(items))
+    }
+
+    fn visit_map<A>(self, mut map: A) -> std::result::Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let mut mapping = yaml::Mapping::new();
+        while let Some(Parsed(key)) = map.next_key()? {
+            // How `serde_json` passes a number with the `arbitrary_precision`
+            // feature, as a map of a private key to the number as written.
+            #[cfg(feature = "arbitrary_precision")]
+            if mapping.is_empty() && key.as_str() == Some("$serde_json::private::Number")
+            {
+                let text: String = map.next_value()?;
+                let Parsed(number) = yaml::from_str(&text).map_err(de::Error::custom)?;
+                return Ok(number);
+            }
+
+            match mapping.entry(key) {
+                yaml::mapping::Entry::Occupied(entry) => {
+                    let key = match entry.key() {
+                        yaml::Value::Null => "with null key".to_string(),
+                        yaml::Value::Bool(b) => format!("with key `{b}`"),
+                        yaml::Value::Number(n) => format!("with key {n}"),
+                        yaml::Value::String(s) => format!("with key {s:?}"),
+                        _ => "in YAML map".to_string(),
+                    };
+                    return Err(de::Error::custom(format!("duplicate entry {key}")));
+                }
+                yaml::mapping::Entry::Vacant(entry) => {
+                    let Parsed(value) = map.next_value()?;
+                    entry.insert(value);
+                }
+            }
+        }
+        Ok(yaml::Value::Mapping(mapping))
+    }
+
+    fn visit_enum<A>(self, data: A) -> std::result::Result<Self::Value, A::Error>
+    where
+        A: EnumAccess<'de>,
+    {
+        // How `serde_yaml` passes a tagged value, e.g. `!tag value`
+        let (tag, contents) = data.variant::<String>()?;
+        let Parsed(value) = contents.newtype_variant()?;
+        let tag = yaml::value::Tag::new(tag);
+        Ok(yaml::Value::Tagged(Box::new(yaml::value::TaggedValue {
+            tag,
+            value,
+        })))
+    }
+}
+
+/// A rendered template parsed into a YAML value, where the numbers can be kept as
+/// written, e.g. `1.50` or `12345678901234567890.123`.
+///
+/// Such a number is replaced in the value by a string token, i.e. the prefix and
+/// the index of the number, and the tokens are replaced back once the value is
+/// formatted.
+#[derive(Debug)]
+struct Document {
+    value: yaml::Value,
+    numbers: Vec<String>,
+    /// The prefix of the tokens, that no string in the value contains.
+    prefix: String,
+}
+
+impl Document {
+    /// Keep the numbers of the value as written in the text it was parsed from, if
+    /// `exact_numbers` is set.
+    fn new(value: yaml::Value, text: &str, exact_numbers: bool) -> Self {
+        let mut document = Self {
+            value,
+            numbers: vec![],
+            prefix: String::new(),
+        };
+        if !exact_numbers {
+            return document;
+        }
+
+        let mut prefix = "jf-number-".to_string();
+        while has_text(&document.value, &prefix) {
+            prefix.push('-');
+        }
+
+        // Parse the text again as YAML, which gives the numbers as written. If it
+        // does not parse into the same value, which should not happen, the numbers
+        // are kept as parsed.
+        let mut value = document.value.clone();
+        let mut numbers = vec![];
+        let seed = ExactNumbers {
+            value: &mut value,
+            numbers: &mut numbers,
+            prefix: &prefix,
+        };
+        if seed.deserialize(yaml::Deserializer::from_str(text)).is_ok() {
+            document = Self {
+                value,
+                numbers,
+                prefix,
+            };
+        }
+        document
+    }
+
+    /// Replace the tokens in the formatted value by the numbers as written, along
+    /// with the quotes around them if `quoted`, e.g. in JSON.
+    fn restore(&self, formatted: String, quoted: bool) -> String {
+        if self.numbers.is_empty() {
+            return formatted;
+        }
+
+        let mut restored = String::with_capacity(formatted.len());
+        let mut rest = formatted.as_str();
+        while let Some(start) = rest.find(&self.prefix) {
+            let mut before = &rest[..start];
+            rest = &rest[start + self.prefix.len()..];
+            let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+            let index: usize = rest[..digits].parse().unwrap_or_default();
+            rest = &rest[digits..];
+            if quoted {
+                before = before.strip_suffix('"').unwrap_or(before);
+                rest = rest.strip_prefix('"').unwrap_or(rest);
+            }
+            restored.push_str(before);
+            restored.push_str(&self.numbers[index]);
+        }
+        restored.push_str(rest);
+        restored
+    }
+}
+
+/// Check if any string in the value, including the keys and tags, contains the text.
+fn has_text(value: &yaml::Value, text: &str) -> bool {
+    match value {
+        yaml::Value::String(s) => s.contains(text),
+        yaml::Value::Sequence(items) => items.iter().any(|item| has_text(item, text)),
+        yaml::Value::Mapping(mapping) => mapping
+            .iter()
+            .any(|(key, value)| has_text(key, text) || has_text(value, text)),
+        yaml::Value::Tagged(tagged) => {
+            tagged.tag.to_string().contains(text) || has_text(&tagged.value, text)
+        }
+        _ => false,
+    }
+}
+
+/// Deserialize the text of a parsed value again, replacing each number that is not
+/// written the way it would be formatted, e.g. `1.50` or `1E5`, by a token.
+struct ExactNumbers<'v> {
+    value: &'v mut yaml::Value,
+    numbers: &'v mut Vec<String>,
+    prefix: &'v str,
+}
+
+impl<'de> DeserializeSeed<'de> for ExactNumbers<'_> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> std::result::Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let Self {
+            value,
+            numbers,
+            prefix,
+        } = self;
+
+        match value {
+            yaml::Value::Sequence(items) => {
+                deserializer.deserialize_seq(ExactItems(items, numbers, prefix))
+            }
+            yaml::Value::Mapping(mapping) => {
+                deserializer.deserialize_map(ExactEntries(mapping, numbers, prefix))
+            }
+            yaml::Value::Number(number) => {
+                let formatted = json::to_string(number).ok();
+                let Some(text) = deserializer.deserialize_str(NumberText)? else {
+                    return Ok(());
+                };
+                // Only a number that is valid JSON, e.g. not `0x1F` or `.inf`
+                let is_json = text.starts_with(|c: char| c == '-' || c.is_ascii_digit())
+                    && json::from_str::<de::IgnoredAny>(&text).is_ok();
+                if is_json && formatted.as_ref() != Some(&text) {
+                    *value = yaml::Value::String(format!("{prefix}{}", numbers.len()));
+                    numbers.push(text);
+                }
+                Ok(())
+            }
+            _ => de::IgnoredAny::deserialize(deserializer).map(|_| ()),
+        }
+    }
+}
+
+/// The items of a sequence, for [`ExactNumbers`].
+struct ExactItems<'v>(&'v mut Vec<yaml::Value>, &'v mut Vec<String>, &'v str);
+
+impl<'de> Visitor<'de> for ExactItems<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a sequence of {} items", self.0.len())
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<(), A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let Self(items, numbers, prefix) = self;
+        for value in items.iter_mut() {
+            let seed = ExactNumbers {
+                value,
+                numbers: &mut *numbers,
+                prefix,
+            };
+            if seq.next_element_seed(seed)?.is_none() {
+                return Err(de::Error::custom("missing items"));
+            }
+        }
+        match seq.next_element::<de::IgnoredAny>()? {
+            Some(_) => Err(de::Error::custom("extra items")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The entries of a mapping, for [`ExactNumbers`]. Only the values can be replaced.
+struct ExactEntries<'v>(&'v mut yaml::Mapping, &'v mut Vec<String>, &'v str);
+
+impl<'de> Visitor<'de> for ExactEntries<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a mapping of {} entries", self.0.len())
+    }
+
+    fn visit_map<A>(self, mut map: A) -> std::result::Result<(), A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let Self(mapping, numbers, prefix) = self;
+        for value in mapping.values_mut() {
+            if map.next_key::<de::IgnoredAny>()?.is_none() {
+                return Err(de::Error::custom("missing entries"));
+            }
+            map.next_value_seed(ExactNumbers {
+                value,
+                numbers: &mut *numbers,
+                prefix,
+            })?;
+        }
+        match map.next_key::<de::IgnoredAny>()? {
+            Some(_) => Err(de::Error::custom("extra entries")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The text of a number as written, if the deserializer gives it, as `serde_yaml`.
+struct NumberText;
+
+impl<'de> Visitor<'de> for NumberText {
+    type Value = Option<String>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a number")
+    }
+
+    fn visit_str<E>(self, s: &str) -> std::result::Result<Self::Value, E> {
+        Ok(Some(s.to_string()))
+    }
+
+    fn visit_i64<E>(self, _: i64) -> std::result::Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_u64<E>(self, _: u64) -> std::result::Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_f64<E>(self, _: f64) -> std::result::Result<Self::Value, E> {
+        Ok(None)
+    }
+}
+
+/// Convert the YAML value into a TOML value, reporting the path of the values that
+/// TOML cannot represent.
+fn to_toml(value: yaml::Value, path: &str) -> std::result::Result<toml::Value, String> {
+    let at = || match path {
+        "" => "at the top level".to_string(),
+        path => format!("at '{path}'"),
+    };
+
+    match value {
+        yaml::Value::Null => Err(format!("cannot represent null {}", at())),
+        yaml::Value::Bool(b) => Ok(toml::Value::Boolean(b)),
+        yaml::Value::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => Ok(toml::Value::Integer(i)),
+            (None, Some(f)) if n.is_f64() => Ok(toml::Value::Float(f)),
+            _ => Err(format!(
+                "cannot represent integer {n} {}, it is out of range",
+                at()
+            )),
+        },
+        yaml::Value::String(s) => Ok(toml::Value::String(s)),
+        yaml::Value::Sequence(items) => {
+            let items = items
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| to_toml(item, &format!("{path}[{i}]")))
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+
+            if let Some(item) =
+                items.iter().find(|i| i.type_str() != items[0].type_str())
+            {
+                return Err(format!(
+                    "cannot represent array with mixed types {}, found {} and {}",
+                    at(),
+                    items[0].type_str(),
+                    item.type_str()
+                ));
+            }
+            Ok(toml::Value::Array(items))
+        }
+        yaml::Value::Mapping(pairs) => {
+            let mut table = toml::Table::new();
+            for (key, value) in pairs {
+                let yaml::Value::String(key) = key else {
+                    let key = json::to_string(&key).map_err(|e| e.to_string())?;
+                    return Err(format!(
+                        "cannot represent non-string key {key} {}",
+                        at()
+                    ));
+                };
+                let path = match path {
+                    "" => key.clone(),
+                    path => format!("{path}.{key}"),
+                };
+                table.insert(key, to_toml(value, &path)?);
+            }
+            Ok(toml::Value::Table(table))
+        }
+        yaml::Value::Tagged(tagged) => Err(format!(
+            "cannot represent tagged value {} {}",
+            tagged.tag,
+            at()
+        )),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DefaultValue {
+    Value(String),
+    File(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Parse names like `foo`, `foo.bar` and `foo[0].bar` into the path of the value.
+fn parse_path(name: &str) -> Option<Vec<Segment>> {
+    let is_key =
+        |k: &str| !k.is_empty() && k.chars().all(|c| c.is_alphanumeric() || c == '_');
+    let is_index = |i: &str| !i.is_empty() && i.chars().all(|c| c.is_ascii_digit());
+    let mut path = vec![];
+
+    for part in name.split('.') {
+        let (key, indices) = match part.split_once('[') {
+            Some((key, indices)) => (key, Some(indices.strip_suffix(']')?)),
+            None => (part, None),
+        };
+
+        if !is_key(key) {
+            return None;
+        }
+        path.push(Segment::Key(key.into()));
+
+        for index in indices.into_iter().flat_map(|i| i.split("][")) {
+            if !is_index(index) {
+                return None;
+            }
+            path.push(Segment::Index(index.parse().ok()?));
+        }
+    }
+
+    Some(path)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Named {
+    name: String,
+    path: Vec<Segment>,
+    default: Option<DefaultValue>,
+    is_nullable: bool,
+    is_optional: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Source {
+    Args,
+    Stdin,
+    Named(Named),
+    Env(Named),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Placeholder {
+    source: Source,
+    expansion: Expansion,
+    specifier: Specifier,
+    span: Span,
+    /// The quote of the quoted scalar the placeholder is in, if any.
+    quote: Option<char>,
+}
+
+impl Placeholder {
+    /// Describe the placeholder, given its position among the positional
+    /// placeholders reading the same input.
+    fn info(&self, index: Option<usize>) -> PlaceholderInfo {
+        let mut info = PlaceholderInfo {
+            span: self.span.clone(),
+            input: "args",
+            index,
+            name: None,
+            specifier: self.specifier.as_char(),
+            expansion: self.expansion.stars(),
+            default: None,
+            default_file: None,
+            nullable: false,
+            optional: false,
+        };
+
+        let named = match &self.source {
+            Source::Args => None,
+            Source::Stdin => {
+                info.input = "stdin";
+                None
+            }
+            Source::Named(named) => Some(named),
+            Source::Env(named) => {
+                info.input = "env";
+                Some(named)
+            }
+        };
+
+        if let Some(named) = named {
+            info.name = Some(named.name.clone());
+            match &named.default {
+                Some(DefaultValue::Value(v)) => info.default = Some(v.clone()),
+                Some(DefaultValue::File(f)) => info.default_file = Some(f.clone()),
+                None => {}
+            }
+            info.nullable = named.is_nullable;
+            info.optional = named.is_optional;
+        }
+
+        info
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    /// The literal text, and the char offset where it starts in the template.
+    Literal(String, usize),
+    Placeholder(Placeholder),
+}
+
+#[derive(Debug)]
+enum Origin<'t> {
+    /// The char offset in the template of the first rendered char.
+    Literal(usize),
+    /// A value substituted for the placeholder.
+    Value(&'t Span, ValueSource),
+}
+
+/// Where each range of the rendered string came from in the template.
+#[derive(Debug, Default)]
+struct SourceMap<'t> {
+    ranges: Vec<(Range<usize>, Origin<'t>)>,
+}
+
+impl SourceMap<'_> {
+    /// Find the location in the template that produced the text at the given
+    /// byte index of the rendered string.
+    ///
+    /// An index right after a substituted value is attributed to the value, as
+    /// that is where the parsers usually notice a bad value.
+    fn locate(
+        &self,
+        template: &str,
+        rendered: &str,
+        index: usize,
+    ) -> Option<(Span, Option<ValueSource>)> {
+        let value = self.ranges.iter().find_map(|(range, origin)| match origin {
+            Origin::Value(span, source)
+                if !range.is_empty() && range.start <= index && index <= range.end =>
+            {
+                Some((*span, *source))
+            }
+            _ => None,
+        });
+
+        if let Some((span, source)) = value {
+            return Some((span.clone(), Some(source)));
+        }
+
+        let (range, start) =
+            self.ranges
+                .iter()
+                .find_map(|(range, origin)| match origin {
+                    Origin::Literal(start)
+                        if range.contains(&index) || range.end == index =>
+                    {
+                        Some((range, start))
+                    }
+                    _ => None,
+                })?;
+
+        // Each '%' in the rendered literal was escaped as '%%' in the template
+        let text = rendered.get(range.start..index)?;
+        let offset = start + text.chars().count() + text.matches('%').count();
+        let end = template.chars().count().min(offset + 1);
+        Some((Span::new(template, offset..end, offset), None))
+    }
+}
+
+/// Where the rendered string goes.
+enum Sink<'t> {
+    /// Keep the whole string, to parse it afterwards.
+    Buffer,
+    /// Write the string to the writer as it is rendered.
+    Raw(&'t mut dyn io::Write),
+    /// Write each value of the only array expansion in the template to the writer
+    /// as JSON items, counting the items written so far.
+    Items(&'t mut dyn io::Write, usize),
+}
+
+/// The rendered string, and where each part of it came from.
+///
+/// When streaming to a writer, only the parts that are not written yet are kept,
+/// and there is no source map.
+struct Rendered<'t> {
+    val: String,
+    map: SourceMap<'t>,
+    strict: bool,
+    json_template: bool,
+    exact_numbers: bool,
+    /// The quote around the placeholder being rendered, if any.
+    quote: Option<char>,
+    sink: Sink<'t>,
+}
+
+impl<'t> Rendered<'t> {
+    fn new(
+        strict: bool,
+        json_template: bool,
+        exact_numbers: bool,
+        sink: Sink<'t>,
+    ) -> Self {
+        Self {
+            val: String::new(),
+            map: SourceMap::default(),
+            strict,
+            json_template,
+            exact_numbers,
+            quote: None,
+            sink,
+        }
+    }
+
+    fn push_literal(&mut self, literal: &str, start: usize) -> Result<()> {
+        match &mut self.sink {
+            Sink::Buffer => {
+                let range_start = self.val.len();
+                self.val.push_str(literal);
+                let range = range_start..self.val.len();
+                self.map.ranges.push((range, Origin::Literal(start)));
+            }
+            Sink::Raw(writer) => {
+                writer.write_all(self.val.as_bytes())?;
+                writer.write_all(literal.as_bytes())?;
+                self.val.clear();
+            }
+            // Only the brackets around the items, written by `Template::format_to`
+            Sink::Items(..) => self.val.clear(),
+        }
+        Ok(())
+    }
+
+    fn push_value(
+        &mut self,
+        specifier: Specifier,
+        arg: &str,
+        span: &'t Span,
+        source: ValueSource,
+    ) -> Result<()> {
+        match &mut self.sink {
+            Sink::Buffer => {
+                let range_start = self.val.len();
+                specifier.push(&mut self.val, arg, span, self.strict, self.quote)?;
+                let range = range_start..self.val.len();
+                self.map.ranges.push((range, Origin::Value(span, source)));
+            }
+            Sink::Raw(writer) => {
+                specifier.push(&mut self.val, arg, span, self.strict, self.quote)?;
+                writer.write_all(self.val.as_bytes())?;
+                self.val.clear();
+            }
+            Sink::Items(writer, count) => {
+                // The separators are written along with the items
+                self.val.clear();
+                self.val.push('[');
+                specifier.push(&mut self.val, arg, span, self.strict, self.quote)?;
+                self.val.push(']');
+
+                // Like in the whole array, a value can be any number of items.
+                let Parsed(items) = match json::from_str(&self.val) {
+                    Ok(items) => items,
+                    Err(error) if self.json_template => return Err(error.into()),
+                    Err(_) => yaml::from_str(&self.val).map_err(|error| {
+                        let span = Some(span.clone());
+                        let value = Some(source);
+                        Error::Yaml(Box::new(YamlError { error, span, value }))
+                    })?,
+                };
+                let items = Document::new(items, &self.val, self.exact_numbers);
+                let yaml::Value::Sequence(values) = &items.value else {
+                    unreachable!("a value between brackets is a sequence");
+                };
+                for value in values {
+                    if *count > 0 {
+                        writer.write_all(b",")?;
+                    }
+                    let item = items.restore(json::to_string(value)?, true);
+                    writer.write_all(item.as_bytes())?;
+                    *count += 1;
+                }
+                self.val.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Write what is left to the writer, if any.
+    fn finish(self) -> Result<()> {
+        if let Sink::Raw(writer) = self.sink {
+            writer.write_all(self.val.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// A parsed template that can be rendered any number of times with different values.
+///
+/// Parsing validates the placeholder syntax up front, so that rendering only
+/// needs to deal with the values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+/// A placeholder in the template and the value it expects, as listed by
+/// [`Template::placeholders`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlaceholderInfo {
+    /// Where the placeholder is in the template.
+    pub span: Span,
+    /// Where the value is read from, one of `args`, `stdin` or `env`.
+    pub input: &'static str,
+    /// The position of a positional placeholder among the ones reading the same
+    /// input, starting from 1.
+    pub index: Option<usize>,
+    /// The name of a named or environment variable placeholder.
+    pub name: Option<String>,
+    /// The specifier, e.g. `s` or `q`.
+    pub specifier: char,
+    /// `*` for array items, `**` for key value pairs, or empty.
+    pub expansion: &'static str,
+    /// The default value, e.g. `bar` for `%(foo=bar)q`.
+    pub default: Option<String>,
+    /// The file to read the default value from, e.g. `-` for `%(foo@-)q`.
+    pub default_file: Option<String>,
+    /// Whether the placeholder defaults to null.
+    pub nullable: bool,
+    /// Whether the placeholder defaults to blank.
+    pub optional: bool,
+}
+
+/// How to split stdin into the values for the stdin placeholders.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Delimiter {
+    /// Values separated by null bytes (`\0`).
+    #[default]
+    Nul,
+    /// Values separated by new lines, with or without carriage returns (`\r\n`).
+    Newline,
+    /// Values separated by the given bytes, e.g. `b", "`.
+    Bytes(Vec<u8>),
+    /// The whole stdin as a single value.
+    Whole,
+    /// The fields of the CSV records, with the given field delimiter, e.g. `b'\t'`
+    /// for TSV.
+    ///
+    /// With a header, the values are the records instead, each as a JSON object of
+    /// the column names to the fields, and the columns are also the named values,
+    /// e.g. `%(name)q` reads the first field in the `name` column and `%(name)*q`
+    /// reads the whole column.
+    Csv { delimiter: u8, header: bool },
+}
+
+impl Delimiter {
+    /// Split the reader into values. A delimiter at the end does not start an
+    /// empty value, and an empty reader has no values.
+    fn split<'a>(
+        &self,
+        mut reader: Box<dyn BufRead + 'a>,
+    ) -> Box<dyn Iterator<Item = io::Result<Vec<u8>>> + 'a> {
+        match self {
+            Self::Nul => Box::new(reader.split(b'\0')),
+            Self::Newline => Box::new(reader.split(b'\n').map(|value| {
+                value.map(|mut v| {
+                    if v.ends_with(b"\r") {
+                        v.pop();
+                    }
+                    v
+                })
+            })),
+            Self::Bytes(delimiter) if delimiter.is_empty() => Self::Whole.split(reader),
+            Self::Bytes(delimiter) if delimiter.len() == 1 => {
+                Box::new(reader.split(delimiter[0]))
+            }
+            Self::Bytes(delimiter) => {
+                let delimiter = delimiter.clone();
+                let last = delimiter[delimiter.len() - 1];
+                let mut is_done = false;
+                Box::new(std::iter::from_fn(move || {
+                    let mut value = vec![];
+                    while !is_done {
+                        match reader.read_until(last, &mut value) {
+                            Ok(_) if value.ends_with(&delimiter) => {
+                                value.truncate(value.len() - delimiter.len());
+                                return Some(Ok(value));
+                            }
+                            Ok(n) if n > 0 && value.ends_with(&[last]) => continue,
+                            Ok(_) => is_done = true,
+                            Err(e) => {
+                                is_done = true;
+                                return Some(Err(e));
+                            }
+                        }
+                    }
+                    Some(Ok(value)).filter(|v| !matches!(v, Ok(v) if v.is_empty()))
+                }))
+            }
+            Self::Csv { delimiter, .. } => {
+                let fields =
+                    csv_records(reader, *delimiter).flat_map(|record| match record {
+                        Ok(record) => {
+                            record.into_iter().map(|f| Ok(f.into_bytes())).collect()
+                        }
+                        Err(e) => vec![Err(e)],
+                    });
+                Box::new(fields)
+            }
+            Self::Whole => {
+                let mut is_done = false;
+                Box::new(std::iter::from_fn(move || {
+                    if is_done {
+                        return None;
+                    }
+                    is_done = true;
+                    let mut value = vec![];
+                    match reader.read_to_end(&mut value) {
+                        Ok(0) => None,
+                        Ok(_) => Some(Ok(value)),
+                        Err(e) => Some(Err(e)),
+                    }
+                }))
+            }
+        }
+    }
+}
+
+/// Read the CSV records with the given field delimiter, following RFC 4180.
+fn csv_records<'a>(
+    reader: Box<dyn BufRead + 'a>,
+    delimiter: u8,
+) -> impl Iterator<Item = io::Result<Vec<String>>> + 'a {
+    csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader)
+        .into_records()
+        .map(|record| {
+            record
+                .map(|r| r.iter().map(String::from).collect())
+                .map_err(io::Error::from)
+        })
+}
+
+/// Read the CSV records after the header as JSON objects, adding the columns to
+/// the named values that are not set yet.
+fn csv_table(
+    reader: Box<dyn BufRead + '_>,
+    delimiter: u8,
+    named_values: &mut NamedValues,
+) -> io::Result<Vec<Vec<u8>>> {
+    let mut records = csv_records(reader, delimiter);
+    let header = records.next().transpose()?.unwrap_or_default();
+    let mut objects = vec![];
+    let mut columns: Vec<Vec<json::Value>> = vec![vec![]; header.len()];
+
+    for (i, record) in records.enumerate() {
+        let record = record?;
+        if record.len() > header.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "CSV record {} has {} fields, but the header has {}",
+                    i + 2,
+                    record.len(),
+                    header.len()
+                ),
+            ));
+        }
+        // A short record is padded with empty fields, so that the columns align
+        let mut fields = record.into_iter();
+        let mut object = vec![];
+        for (name, column) in header.iter().zip(&mut columns) {
+            let field = fields.next().unwrap_or_default();
+            object.push(format!(
+                "{}:{}",
+                json::to_string(name)?,
+                json::to_string(&field)?
+            ));
+            column.push(json::Value::String(field));
+        }
+        objects.push(format!("{{{}}}", object.join(",")).into_bytes());
+    }
+
+    for (name, column) in header.into_iter().zip(columns) {
+        named_values.entry(name).or_insert(column);
+    }
+    Ok(objects)
+}
+
+/// Asks for the value of a named placeholder, given the error for the previous
+/// answer, if it was invalid.
+type Prompt<'a> =
+    Box<dyn FnMut(&PlaceholderInfo, Option<&Error>) -> io::Result<Option<String>> + 'a>;
+
+/// Options to customize how a template is rendered.
+///
+/// By default, the values for the stdin placeholders (e.g. `%-s`, `%*-s` and
+/// `NAME@-`) are read from the process stdin, separated by null bytes.
+#[derive(Default)]
+pub struct Options<'a> {
+    stdin: Option<Box<dyn BufRead + 'a>>,
+    values: NamedValues,
+    env: Option<HashMap<String, String>>,
+    strict: bool,
+    json_template: bool,
+    exact_numbers: bool,
+    prompt: Option<Prompt<'a>>,
+    delimiter: Delimiter,
+}
+
+impl<'a> Options<'a> {
+    /// Read the stdin values from the given reader instead of the process stdin.
+    pub fn stdin<R>(mut self, reader: R) -> Self
+    where
+        R: BufRead + 'a,
+    {
+        self.stdin = Some(Box::new(reader));
+        self
+    }
+
+    /// Never read the process stdin, the stdin placeholders will behave as if
+    /// stdin is empty.
+    pub fn no_stdin(self) -> Self {
+        self.stdin(io::empty())
+    }
+
+    /// Split stdin into values using the given delimiter instead of null bytes.
+    pub fn delimiter(mut self, delimiter: Delimiter) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// In strict mode, every value substituted by an `s` placeholder (e.g. `%s`,
+    /// `%(NAME)s`, `%*-s`) must parse as exactly one YAML value, so that untrusted
+    /// input cannot inject keys, items or other structure into the document. A
+    /// value inside quotes in the template, e.g. `"%s"`, must not close them.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Expect the rendered template to be JSON, e.g. `{"a": %q}`, and report it as a
+    /// JSON error when it is not, instead of parsing it as YAML.
+    ///
+    /// A rendered template that is valid JSON is always parsed as JSON, which is
+    /// faster than parsing it as YAML, and gives the same value.
+    pub fn json_template(mut self, json_template: bool) -> Self {
+        self.json_template = json_template;
+        self
+    }
+
+    /// Keep the numbers as written in the JSON and YAML output, e.g. `1.50` or
+    /// `12345678901234567890.123`, instead of formatting them as 64-bit integers
+    /// and floats. TOML output always formats them.
+    ///
+    /// The numbers of the named values are only kept as written with the
+    /// `arbitrary_precision` feature.
+    pub fn exact_numbers(mut self, exact_numbers: bool) -> Self {
+        self.exact_numbers = exact_numbers;
+        self
+    }
+
+    /// Read the environment variable placeholders (e.g. `%($HOME)q`) from the
+    /// given variables instead of the process environment.
+    pub fn env<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let vars = vars.into_iter().map(|(k, v)| (k.into(), v.into()));
+        self.env = Some(vars.collect());
+        self
+    }
+
+    /// Use the fields of the given map or struct as the named values.
+    ///
+    /// Strings are used as is, other values are used as JSON. Arrays and objects
+    /// can be expanded using `%(NAME)*s` and `%(NAME)**s`, and null values are
+    /// treated as missing. Nested names like `%(NAME.KEY)s` and `%(NAME[0])s` read
+    /// the fields and items of these values. Values passed using the `NAME=VALUE`
+    /// or `NAME@FILE` syntax take precedence over these values.
+    pub fn values<T>(mut self, values: &T) -> Result<Self>
+    where
+        T: Serialize + ?Sized,
+    {
+        let json::Value::Object(values) = json::to_value(values)? else {
+            return Err("named values must be a map of names to values".into());
+        };
+
+        for (name, value) in values {
+            if value.is_null() {
+                self.values.remove(&name);
+            } else {
+                self.values.insert(name, vec![value]);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Ask for the value of each named placeholder that has no value, e.g. to
+    /// prompt the user in a terminal.
+    ///
+    /// The prompt is called again with the error if the answer is not valid for
+    /// the placeholder. Answering `None` leaves the value missing, so that the
+    /// default value, null or blank is used, if allowed. Expandable placeholders
+    /// are never prompted for.
+    pub fn prompt<F>(mut self, prompt: F) -> Self
+    where
+        F: FnMut(&PlaceholderInfo, Option<&Error>) -> io::Result<Option<String>> + 'a,
+    {
+        self.prompt = Some(Box::new(prompt));
+        self
+    }
+}
+
+/// Prompt for the value of the named placeholder until a valid one is given.
+fn prompt_value(
+    prompt: &mut Prompt,
+    placeholder: &Placeholder,
+    strict: bool,
+) -> Result<Option<String>> {
+    let info = placeholder.info(None);
+    let mut error = None;
+
+    loop {
+        let Some(value) = prompt(&info, error.as_ref())? else {
+            return Ok(None);
+        };
+
+        let mut val = String::new();
+        match placeholder.specifier.push(
+            &mut val,
+            &value,
+            &placeholder.span,
+            strict,
+            placeholder.quote,
+        ) {
+            Ok(()) => return Ok(Some(value)),
+            Err(e) => error = Some(e),
+        }
+    }
+}
+
+fn read_to_string<S>(path: &str, stdin: &mut S) -> Result<String>
+where
+    S: Iterator<Item = (usize, io::Result<Vec<u8>>)>,
+{
+    if path == "-" {
+        match stdin.next() {
+            Some((_, Ok(bytes))) => Ok(String::from_utf8_lossy(&bytes).to_string()),
+            Some((_, Err(e))) => Err(e.into()),
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "unexpected end of input",
+            )
+            .into()),
+        }
+    } else {
+        fs::read_to_string(path).map_err(Into::into)
+    }
+}
+
+fn read_brace_value<C>(chars: &mut C) -> String
+where
+    C: Iterator<Item = (usize, char)>,
+{
+    // Reading a default value for a named placeholder
+
+    let mut last_char = None;
+    let mut val = String::new();
+
+    for (_, ch) in chars {
+        match (ch, last_char) {
+            (_, Some('\\')) => {
+                val.push(ch);
+                last_char = None;
+            }
+            ('\\', _) => {
+                last_char = Some(ch);
+            }
+            (')', _) => {
+                break;
+            }
+            (_, _) => {
+                val.push(ch);
+                last_char = None;
+            }
+        }
+    }
+
+    val
+}
+
+fn incomplete_placeholder(template: &str, start: usize) -> TemplateError {
+    let end = template.chars().count();
+    let span = Span::new(template, start..end, end - 1);
+    TemplateError::IncompletePlaceholder { span }
+}
+
+/// Parse a named placeholder, pushing the errors in it, if any.
+///
+/// The errors that leave the rest of the placeholder readable, e.g. conflicting
+/// modifiers, do not stop the parsing, so that the following errors get reported
+/// too.
+fn parse_named_placeholder<C>(
+    template: &str,
+    start: usize,
+    chars: &mut C,
+    errors: &mut Vec<TemplateError>,
+) -> Option<Placeholder>
+where
+    C: Iterator<Item = (usize, char)>,
+{
+    // Reading a named placeholder
+
+    let span = |col: usize| Span::new(template, start..col + 1, col);
+    let errors_before = errors.len();
+
+    let mut last_char = None;
+    let mut name = "".to_string();
+    let mut default_value: Option<DefaultValue> = None;
+    let mut is_optional = false;
+    let mut is_nullable = false;
+    let mut is_env = false;
+    let mut expansion = Expansion::None;
+
+    loop {
+        let Some((col, ch)) = chars.next() else {
+            errors.push(incomplete_placeholder(template, start));
+            return None;
+        };
+
+        match (ch, last_char) {
+            ('=', _) if default_value.is_none() => {
+                default_value = Some(DefaultValue::Value(read_brace_value(chars)));
+                last_char = Some(')');
+            }
+            ('@', _) if default_value.is_none() => {
+                default_value = Some(DefaultValue::File(read_brace_value(chars)));
+                last_char = Some(')');
+            }
+            (')', _) => {
+                last_char = Some(ch);
+            }
+            ('?', Some(')')) => {
+                let span = span(col);
+                if default_value.is_some() {
+                    let name = name.clone();
+                    errors.push(TemplateError::OptionalWithDefault { name, span });
+                } else if is_nullable {
+                    let name = name.clone();
+                    errors.push(TemplateError::OptionalAndNullable { name, span });
+                } else {
+                    is_optional = true;
+                }
+            }
+            ('?', None | Some('$')) => {
+                is_nullable = true;
+                last_char = chars.next().map(|(_, ch)| ch);
+                if last_char != Some(')') {
+                    let span = span(col);
+                    errors.push(TemplateError::UnclosedNullable { name, span });
+                    return None;
+                }
+            }
+            ('$', None) if !is_env => {
+                // Either `%($NAME)` or `%(NAME$)`
+                is_env = true;
+                if !name.is_empty() {
+                    last_char = Some(ch);
+                }
+            }
+            ('*', Some(')')) => {
+                expansion = Expansion::Items;
+                last_char = Some(ch);
+            }
+            ('*', Some('*')) => {
+                expansion = Expansion::Pairs;
+                last_char = Some(ch);
+            }
+            (ch, Some('$')) if ch.is_alphanumeric() || ch == '_' => {
+                let span = span(col);
+                errors.push(TemplateError::MisplacedEnvMarker { name, span });
+                return None;
+            }
+            (ch, None) if ch.is_alphanumeric() || "_.[]".contains(ch) => {
+                name.push(ch);
+                last_char = None;
+            }
+            (ch, Some(')')) | (ch, Some('*')) => {
+                let span = span(col);
+                let Some(specifier) = Specifier::from_char(ch) else {
+                    let stars = expansion.stars();
+                    let placeholder = format!("%({name}){stars}{ch}");
+                    errors.push(TemplateError::InvalidNamedPlaceholder {
+                        placeholder,
+                        span,
+                    });
+                    return None;
+                };
+
+                if name.is_empty() {
+                    errors.push(TemplateError::MissingName { span });
+                    return None;
+                }
+
+                let Some(path) = parse_path(&name) else {
+                    errors.push(TemplateError::InvalidName { name, span });
+                    return None;
+                };
+
+                if is_env && path.len() > 1 {
+                    let name = name.clone();
+                    let span = span.clone();
+                    errors.push(TemplateError::NestedEnvName { name, span });
+                }
+
+                if is_env && expansion != Expansion::None {
+                    let name = name.clone();
+                    let span = span.clone();
+                    errors.push(TemplateError::ExpandedEnv { name, span });
+                }
+
+                if expansion != Expansion::None && default_value.is_some() {
+                    let name = name.clone();
+                    let span = span.clone();
+                    errors.push(TemplateError::ExpansionWithDefault { name, span });
+                }
+
+                if errors.len() > errors_before {
+                    return None;
+                }
+
+                let named = Named {
+                    name,
+                    path,
+                    default: default_value,
+                    is_nullable,
+                    is_optional,
+                };
+
+                return Some(Placeholder {
+                    source: if is_env {
+                        Source::Env(named)
+                    } else {
+                        Source::Named(named)
+                    },
+                    expansion,
+                    specifier,
+                    span,
+                    quote: None,
+                });
+            }
+            (_, _) => {
+                let span = span(col);
+                errors.push(TemplateError::InvalidCharacter {
+                    character: ch,
+                    span,
+                });
+                return None;
+            }
+        }
+    }
+}
+
+type NamedValues = HashMap<String, Vec<json::Value>>;
+
+fn value_text(value: &json::Value) -> Cow<'_, str> {
+    match value {
+        json::Value::String(s) => s.into(),
+        value => value.to_string().into(),
+    }
+}
+
+fn expand_value(value: &json::Value) -> Vec<Cow<'_, str>> {
+    // Arrays expand into items, and objects expand into key value pairs
+
+    match value {
+        json::Value::Array(items) => items.iter().map(value_text).collect(),
+        json::Value::Object(pairs) => pairs
+            .iter()
+            .flat_map(|(k, v)| [k.into(), value_text(v)])
+            .collect(),
+        value => vec![value_text(value)],
+    }
+}
+
+/// Find the values for the given path, e.g. `foo.bar` finds `bar` in `foo`.
+fn lookup<'v>(named_values: &'v NamedValues, path: &[Segment]) -> &'v [json::Value] {
+    let Some((Segment::Key(name), path)) = path.split_first() else {
+        return &[];
+    };
+
+    let values = named_values
+        .get(name)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    if path.is_empty() {
+        return values;
+    }
+
+    let value = path.iter().try_fold(values.first(), |value, segment| {
+        Some(match segment {
+            Segment::Key(key) => value?.get(key),
+            Segment::Index(index) => value?.get(index),
+        })
+    });
+
+    match value.flatten() {
+        Some(value) if !value.is_null() => std::slice::from_ref(value),
+        _ => &[],
+    }
+}
+
+/// Set the named value at the given path, e.g. `foo.bar` sets `bar` in `foo`.
+fn insert_value(
+    named_values: &mut NamedValues,
+    path: &[Segment],
+    value: json::Value,
+) -> std::result::Result<(), PathError> {
+    let Some((Segment::Key(name), path)) = path.split_first() else {
+        return Err(PathError::Conflict);
+    };
+
+    let values = named_values.entry(name.clone()).or_default();
+    if path.is_empty() {
+        *values = vec![value];
+        return Ok(());
+    }
+
+    if values.is_empty() {
+        values.push(json::Value::Null);
+    }
+    set_path(&mut values[0], path, value)
+}
+
+/// The largest array index of a nested named value, e.g. `a[65535]=x`, so that an
+/// index cannot allocate an array of any size.
+const MAX_INDEX: usize = 65_535;
+
+/// Why a nested named value cannot be set.
+enum PathError {
+    /// The path goes through a value of another type, e.g. `a[0]` in an object.
+    Conflict,
+    IndexTooLarge,
+}
+
+/// Set the value at the given path, creating the parent objects and arrays.
+fn set_path(
+    target: &mut json::Value,
+    path: &[Segment],
+    value: json::Value,
+) -> std::result::Result<(), PathError> {
+    let Some((segment, path)) = path.split_first() else {
+        *target = value;
+        return Ok(());
+    };
+
+    if target.is_null() {
+        *target = match segment {
+            Segment::Key(_) => json::Value::Object(Default::default()),
+            Segment::Index(_) => json::Value::Array(vec![]),
+        };
+    }
+
+    match (segment, target) {
+        (Segment::Key(key), json::Value::Object(object)) => {
+            let target = object.entry(key.clone()).or_insert(json::Value::Null);
+            set_path(target, path, value)
+        }
+        (Segment::Index(index), json::Value::Array(_)) if *index > MAX_INDEX => {
+            Err(PathError::IndexTooLarge)
+        }
+        (Segment::Index(index), json::Value::Array(items)) => {
+            if items.len() <= *index {
+                items.resize(index + 1, json::Value::Null);
+            }
+            set_path(&mut items[*index], path, value)
+        }
+        (_, _) => Err(PathError::Conflict),
+    }
+}
+
+fn collect_named_values<'a, A, S>(
+    args: &mut A,
+    stdin: &mut S,
+    named_values: &mut NamedValues,
+    value_nos: &mut HashMap<String, Vec<usize>>,
+) -> Result<()>
+where
+    A: Iterator<Item = (usize, Cow<'a, str>)>,
+    S: Iterator<Item = (usize, io::Result<Vec<u8>>)>,
+{
+    let mut overridden = HashSet::new();
+
+    for (valnum, arg) in args.by_ref() {
+        let (name, value) = if let Some((name, value)) = arg.split_once('=') {
+            (name, value.to_string())
+        } else if let Some((name, path)) = arg.split_once('@') {
+            let value = read_to_string(path, stdin)?;
+            (name, value)
+        } else {
+            return Err(TemplateError::InvalidValueSyntax { value_no: valnum }.into());
+        };
+
+        // Values passed as arguments replace the values passed via the options,
+        // except that nested names are set inside the existing objects and arrays.
+        let value = json::Value::String(value);
+        let path = Some(name)
+            .filter(|n| n.contains(['.', '[']))
+            .and_then(parse_path);
+
+        let Some([Segment::Key(root), path @ ..]) = path.as_deref() else {
+            // A value set by a nested name, e.g. `a.b=x`, has no value numbers
+            if overridden.contains(name) && !value_nos.contains_key(name) {
+                let name = name.to_string();
+                return Err(TemplateError::ConflictingName {
+                    name,
+                    value_no: valnum,
+                }
+                .into());
+            }
+            let values = named_values.entry(name.to_string()).or_default();
+            let nos = value_nos.entry(name.to_string()).or_default();
+            if overridden.insert(name.to_string()) {
+                values.clear();
+                nos.clear();
+            }
+            values.push(value);
+            nos.push(valnum);
+            continue;
+        };
+
+        value_nos.remove(root);
+        let values = named_values.entry(root.to_string()).or_default();
+        if overridden.insert(root.to_string())
+            && !matches!(
+                values.as_slice(),
+                [json::Value::Object(_) | json::Value::Array(_)]
+            )
+        {
+            values.clear();
+        }
+
+        if values.is_empty() {
+            values.push(json::Value::Null);
+        }
+
+        let name = name.to_string();
+        match set_path(&mut values[0], path, value) {
+            Ok(()) => {}
+            Err(PathError::Conflict) => {
+                return Err(TemplateError::ConflictingName {
+                    name,
+                    value_no: valnum,
+                }
+                .into());
+            }
+            Err(PathError::IndexTooLarge) => {
+                return Err(TemplateError::IndexTooLarge {
+                    name,
+                    value_no: valnum,
+                    max: MAX_INDEX,
+                }
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read the next value, if any, where reading stdin can fail, e.g. on an invalid
+/// CSV record.
+fn read_next<'a, A, S>(
+    is_stdin: bool,
+    args: &mut A,
+    stdin: &mut S,
+) -> Result<Option<(usize, String)>>
+where
+    A: Iterator<Item = (usize, Cow<'a, str>)>,
+    S: Iterator<Item = (usize, io::Result<Vec<u8>>)>,
+{
+    if is_stdin {
+        if let Some((i, arg)) = stdin.next() {
+            let arg = arg?;
+            let arg = String::from_utf8_lossy(&arg).to_string();
+            Ok(Some((i, arg)))
+        } else {
+            Ok(None)
+        }
+    } else {
+        Ok(args.next().map(|(i, a)| (i, a.to_string())))
+    }
+}
+
+fn read<'a, A, S>(
+    is_stdin: bool,
+    span: &Span,
+    args: &mut A,
+    stdin: &mut S,
+) -> Result<(usize, String)>
+where
+    A: Iterator<Item = (usize, Cow<'a, str>)>,
+    S: Iterator<Item = (usize, io::Result<Vec<u8>>)>,
+{
+    if let Some((i, arg)) = read_next(is_stdin, args, stdin)? {
+        Ok((i, arg))
+    } else {
+        let span = span.clone();
+        Err(TemplateError::MissingValue { span }.into())
+    }
+}
+
+/// The source of the value no. `i` read by a positional placeholder.
+fn positional_source(is_stdin: bool, i: usize) -> ValueSource {
+    if is_stdin {
+        ValueSource::Stdin(i + 1)
+    } else {
+        ValueSource::Arg(i)
+    }
+}
+
+fn read_positional_placeholder<'a, 't, A, S, F>(
+    out: &mut Rendered<'t>,
+    specifier: Specifier,
+    span: &'t Span,
+    is_stdin: bool,
+    args: &mut A,
+    stdin: &mut S,
+    source: F,
+) -> Result<()>
+where
+    A: Iterator<Item = (usize, Cow<'a, str>)>,
+    S: Iterator<Item = (usize, io::Result<Vec<u8>>)>,
+    F: Fn(usize) -> ValueSource,
+{
+    let (i, arg) = read(is_stdin, span, args, stdin)?;
+    out.push_value(specifier, &arg, span, source(i))
+}
+
+fn read_positional_items_placeholder<'a, 't, A, S, F>(
+    out: &mut Rendered<'t>,
+    specifier: Specifier,
+    span: &'t Span,
+    is_stdin: bool,
+    args: &mut A,
+    stdin: &mut S,
+    source: F,
+) -> Result<bool>
+where
+    A: Iterator<Item = (usize, Cow<'a, str>)>,
+    S: Iterator<Item = (usize, io::Result<Vec<u8>>)>,
+    F: Fn(usize) -> ValueSource,
+{
+    let mut was_expanded = false;
+
+    while let Some((i, arg)) = read_next(is_stdin, args, stdin)? {
+        was_expanded = true;
+        out.push_value(specifier, &arg, span, source(i))?;
+        out.val.push(',');
+    }
+
+    if was_expanded {
+        out.val.pop();
+    }
+    Ok(was_expanded)
+}
+
+fn read_positional_pairs_placeholder<'a, 't, A, S, F>(
+    out: &mut Rendered<'t>,
+    specifier: Specifier,
+    span: &'t Span,
+    is_stdin: bool,
+    args: &mut A,
+    stdin: &mut S,
+    source: F,
+) -> Result<bool>
+where
+    A: Iterator<Item = (usize, Cow<'a, str>)>,
+    S: Iterator<Item = (usize, io::Result<Vec<u8>>)>,
+    F: Fn(usize) -> ValueSource,
+{
+    let mut is_reading_key = true;
+    let mut was_expanded = false;
+    while let Some((i, arg)) = read_next(is_stdin, args, stdin)? {
+        was_expanded = true;
+
+        if is_reading_key {
+            out.push_value(Specifier::Quoted, &arg, span, source(i))?;
+            out.val.push(':');
+            is_reading_key = false;
+        } else {
+            out.push_value(specifier, &arg, span, source(i))?;
+            out.val.push(',');
+            is_reading_key = true;
+        }
+    }
+
+    if !is_reading_key {
+        let span = span.clone();
+        return Err(TemplateError::MissingValue { span }.into());
+    }
+
+    if was_expanded {
+        out.val.pop();
+    }
+    Ok(was_expanded)
+}
+
+fn read_named_placeholder<'t, S>(
+    out: &mut Rendered<'t>,
+    placeholder: &'t Placeholder,
+    named: &Named,
+    values: &[json::Value],
+    value_nos: &[usize],
+    stdin: &mut S,
+) -> Result<bool>
+where
+    S: Iterator<Item = (usize, io::Result<Vec<u8>>)>,
+{
+    let Placeholder {
+        specifier, span, ..
+    } = placeholder;
+    let specifier = *specifier;
+    let name = match placeholder.source {
+        Source::Env(_) => format!("${}", named.name),
+        _ => named.name.clone(),
+    };
+
+    // Only the values passed as `NAME=VALUE` arguments have a number
+    let source = |i: usize| {
+        value_nos
+            .get(i)
+            .map_or(ValueSource::Other, |n| ValueSource::Arg(*n))
+    };
+
+    let default_value = match &named.default {
+        Some(DefaultValue::Value(value)) => Some(Cow::from(value)),
+        Some(DefaultValue::File(path)) => Some(read_to_string(path, stdin)?.into()),
+        None => None,
+    };
+
+    let mut args = values.iter().flat_map(expand_value).enumerate();
+
+    match placeholder.expansion {
+        Expansion::None => {
+            let maybe_value = values.first().map(value_text).or(default_value);
+
+            if let Some(value) = maybe_value {
+                let source = if values.is_empty() {
+                    ValueSource::Other
+                } else {
+                    source(0)
+                };
+                out.push_value(specifier, &value, span, source)?;
+            } else if named.is_nullable {
+                out.val.push_str("null");
+            } else if !named.is_optional {
+                let ch = specifier.as_char();
+                let placeholder = format!("%({name}){ch}");
+                let span = span.clone();
+                return Err(
+                    TemplateError::MissingNamedValue { placeholder, span }.into()
+                );
+            };
+            Ok(false)
+        }
+        Expansion::Items => Ok(!read_positional_items_placeholder(
+            out, specifier, span, false, &mut args, stdin, source,
+        )?),
+        Expansion::Pairs => Ok(!read_positional_pairs_placeholder(
+            out, specifier, span, false, &mut args, stdin, source,
+        )?),
+    }
+}
+
+impl Template {
+    /// Parse the template, validating all the placeholders in it.
+    ///
+    /// Returns the first error in the template, see [`Template::validate`] to get
+    /// all of them.
+    pub fn parse(template: &str) -> Result<Self> {
+        let (parsed, errors) = Self::parse_all(template);
+        match errors.into_iter().next() {
+            Some(error) => Err(error.into()),
+            None => Ok(parsed),
+        }
+    }
+
+    /// Check the template and return all the errors in it, in order.
+    pub fn validate(template: &str) -> Vec<TemplateError> {
+        Self::parse_all(template).1
+    }
+
+    /// Parse the template, skipping the invalid placeholders. The template is
+    /// only usable if there are no errors.
+    fn parse_all(template: &str) -> (Self, Vec<TemplateError>) {
+        let mut errors = vec![];
+        let mut chars = template.chars().enumerate();
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut last_char = None;
+        let mut is_reading_named_values = false;
+        let mut expansion = Expansion::None;
+        let mut is_stdin = false;
+        let mut start = 0;
+        let mut literal_start = 0;
+
+        while let Some((col, ch)) = chars.next() {
+            let placeholder = match (ch, last_char) {
+                ('%', Some('%')) => {
+                    if literal.is_empty() {
+                        literal_start = col - 1;
+                    }
+                    literal.push(ch);
+                    last_char = None;
+                    None
+                }
+                ('%', _) => {
+                    last_char = Some(ch);
+                    start = col;
+                    None
+                }
+                ('(', Some('%')) => {
+                    let placeholder = parse_named_placeholder(
+                        template,
+                        start,
+                        &mut chars,
+                        &mut errors,
+                    );
+                    if let Some(Placeholder {
+                        source: Source::Named(_),
+                        ..
+                    }) = placeholder
+                    {
+                        is_reading_named_values = true;
+                    }
+                    if placeholder.is_none() {
+                        last_char = None;
+                        expansion = Expansion::None;
+                        is_stdin = false;
+                    }
+                    placeholder
+                }
+                ('*', Some('%')) if expansion == Expansion::None => {
+                    expansion = Expansion::Items;
+                    None
+                }
+                ('*', Some('%')) if expansion == Expansion::Items => {
+                    expansion = Expansion::Pairs;
+                    None
+                }
+                ('-', Some('%')) => {
+                    is_stdin = true;
+                    None
+                }
+                (ch, Some('%')) => {
+                    let span = Span::new(template, start..col + 1, col);
+                    let Some(specifier) = Specifier::from_char(ch) else {
+                        let stars = expansion.stars();
+                        let placeholder = format!("%{stars}{ch}");
+                        errors.push(TemplateError::InvalidPlaceholder {
+                            placeholder,
+                            span,
+                        });
+                        last_char = None;
+                        expansion = Expansion::None;
+                        is_stdin = false;
+                        continue;
+                    };
+
+                    if is_reading_named_values {
+                        let specifier = ch;
+                        let span = span.clone();
+                        errors.push(TemplateError::PositionalAfterNamed {
+                            specifier,
+                            span,
+                        });
+                    };
+
+                    Some(Placeholder {
+                        source: if is_stdin {
+                            Source::Stdin
+                        } else {
+                            Source::Args
+                        },
+                        expansion,
+                        specifier,
+                        span,
+                        quote: None,
+                    })
+                }
+                (_, _) => {
+                    if literal.is_empty() {
+                        literal_start = col;
+                    }
+                    literal.push(ch);
+                    None
+                }
+            };
+
+            if let Some(placeholder) = placeholder {
+                if !literal.is_empty() {
+                    let literal = std::mem::take(&mut literal);
+                    parts.push(Part::Literal(literal, literal_start));
+                }
+                parts.push(Part::Placeholder(placeholder));
+                last_char = None;
+                expansion = Expansion::None;
+                is_stdin = false;
+            }
+        }
+
+        if last_char == Some('%') {
+            errors.push(incomplete_placeholder(template, start));
+        };
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal, literal_start));
+        }
+        mark_quoted(&mut parts);
+
+        let source = template.to_string();
+        (Self { source, parts }, errors)
+    }
+
+    fn render_from<'a, 't, A, S>(
+        &'t self,
+        args: &mut A,
+        stdin: &mut S,
+        options: Options,
+        out: &mut Rendered<'t>,
+    ) -> Result<()>
+    where
+        A: Iterator<Item = (usize, Cow<'a, str>)>,
+        S: Iterator<Item = (usize, io::Result<Vec<u8>>)>,
+    {
+        let Options {
+            values: mut named_values,
+            env,
+            strict,
+            mut prompt,
+            ..
+        } = options;
+        let mut value_nos = HashMap::new();
+        let mut is_reading_named_values = false;
+        let mut empty_expansion = false;
+
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(literal, start) => {
+                    // To allow merging arrays and objects via expansion, remove the
+                    // trailing comma after an expandable placeholder with no values.
+                    let (literal, start) = match literal.strip_prefix(',') {
+                        Some(rest) if empty_expansion => (rest, start + 1),
+                        _ => (literal.as_str(), *start),
+                    };
+                    out.push_literal(literal, start)?;
+                    empty_expansion = false;
+                }
+                Part::Placeholder(placeholder) => {
+                    let Placeholder {
+                        specifier,
+                        expansion,
+                        ref span,
+                        ..
+                    } = *placeholder;
+                    out.quote = placeholder.quote;
+
+                    empty_expansion = match (&placeholder.source, expansion) {
+                        (Source::Named(named), _) => {
+                            if !is_reading_named_values {
+                                is_reading_named_values = true;
+                                collect_named_values(
+                                    args,
+                                    stdin,
+                                    &mut named_values,
+                                    &mut value_nos,
+                                )?;
+                            };
+                            if let (Some(prompt), Expansion::None) =
+                                (&mut prompt, expansion)
+                            {
+                                if lookup(&named_values, &named.path).is_empty() {
+                                    let value =
+                                        prompt_value(prompt, placeholder, strict)?;
+                                    if let Some(value) = value {
+                                        let value = json::Value::String(value);
+                                        // A conflicting name is left as missing
+                                        insert_value(
+                                            &mut named_values,
+                                            &named.path,
+                                            value,
+                                        )
+                                        .ok();
+                                    }
+                                }
+                            }
+                            let values = lookup(&named_values, &named.path);
+                            let nos = match named.path.as_slice() {
+                                [Segment::Key(_)] => value_nos.get(&named.name),
+                                _ => None,
+                            };
+                            read_named_placeholder(
+                                out,
+                                placeholder,
+                                named,
+                                values,
+                                nos.map(Vec::as_slice).unwrap_or_default(),
+                                stdin,
+                            )?
+                        }
+                        (Source::Env(named), _) => {
+                            let value = match &env {
+                                Some(env) => env.get(&named.name).cloned(),
+                                None => std::env::var_os(&named.name)
+                                    .map(|v| v.to_string_lossy().to_string()),
+                            };
+                            let values: Vec<_> =
+                                value.map(json::Value::String).into_iter().collect();
+                            read_named_placeholder(
+                                out,
+                                placeholder,
+                                named,
+                                &values,
+                                &[],
+                                stdin,
+                            )?
+                        }
+                        (source, Expansion::None) => {
+                            let is_stdin = *source == Source::Stdin;
+                            read_positional_placeholder(
+                                out,
+                                specifier,
+                                span,
+                                is_stdin,
+                                args,
+                                stdin,
+                                |i| positional_source(is_stdin, i),
+                            )?;
+                            false
+                        }
+                        (source, Expansion::Items) => {
+                            let is_stdin = *source == Source::Stdin;
+                            !read_positional_items_placeholder(
+                                out,
+                                specifier,
+                                span,
+                                is_stdin,
+                                args,
+                                stdin,
+                                |i| positional_source(is_stdin, i),
+                            )?
+                        }
+                        (source, Expansion::Pairs) => {
+                            let is_stdin = *source == Source::Stdin;
+                            !read_positional_pairs_placeholder(
+                                out,
+                                specifier,
+                                span,
+                                is_stdin,
+                                args,
+                                stdin,
+                                |i| positional_source(is_stdin, i),
+                            )?
+                        }
+                    };
+                }
+            }
+        }
+
+        if args.next().is_some() {
+            return Err(TemplateError::TooManyValues.into());
+        };
+
+        Ok(())
+    }
+
+    /// Render the template into raw string using the given values.
+    pub fn render<'a, I>(&self, args: I) -> Result<String>
+    where
+        I: IntoIterator<Item = Cow<'a, str>>,
+    {
+        self.render_with(args, Options::default())
+    }
+
+    /// Render the template into raw string using the given values and options.
+    pub fn render_with<'a, I>(&self, args: I, options: Options) -> Result<String>
+    where
+        I: IntoIterator<Item = Cow<'a, str>>,
+    {
+        Ok(self.render_mapped(args, options, Sink::Buffer)?.val)
+    }
+
+    /// Render the template into the writer as it reads the values, without keeping
+    /// the whole string in memory.
+    ///
+    /// On error, the output written so far is left incomplete.
+    pub fn render_to<'a, I, W>(
+        &self,
+        args: I,
+        options: Options,
+        mut writer: W,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = Cow<'a, str>>,
+        W: io::Write,
+    {
+        self.render_mapped(args, options, Sink::Raw(&mut writer))?
+            .finish()
+    }
+
+    fn render_mapped<'t, 'a, I>(
+        &'t self,
+        args: I,
+        mut options: Options,
+        sink: Sink<'t>,
+    ) -> Result<Rendered<'t>>
+    where
+        I: IntoIterator<Item = Cow<'a, str>>,
+    {
+        // Values are numbered after the template, starting from 1.
+        let mut args = (1..).zip(args);
+        // Do not hold the stdin lock while rendering, so that a prompt can read
+        // from stdin too.
+        let stdin = options
+            .stdin
+            .take()
+            .unwrap_or_else(|| Box::new(io::BufReader::new(io::stdin())));
+        let stdin = match options.delimiter {
+            Delimiter::Csv {
+                delimiter,
+                header: true,
+            } => {
+                let records = csv_table(stdin, delimiter, &mut options.values)?;
+                Box::new(records.into_iter().map(Ok))
+            }
+            ref delimiter => delimiter.split(stdin),
+        };
+        let mut stdin = stdin.enumerate();
+        let mut out = Rendered::new(
+            options.strict,
+            options.json_template,
+            options.exact_numbers,
+            sink,
+        );
+        self.render_from(&mut args, &mut stdin, options, &mut out)?;
+        Ok(out)
+    }
+
+    /// Render the template and parse it as YAML, pointing the YAML errors back to
+    /// the template.
+    fn render_yaml<'a, I>(&self, args: I, options: Options) -> Result<Document>
+    where
+        I: IntoIterator<Item = Cow<'a, str>>,
+    {
+        let rendered = self.render_mapped(args, options, Sink::Buffer)?;
+        self.parse_rendered(rendered)
+    }
+
+    /// Parse the rendered template as JSON, or else as YAML, pointing the YAML errors
+    /// back to the template.
+    fn parse_rendered(&self, rendered: Rendered) -> Result<Document> {
+        let Rendered {
+            val,
+            map,
+            json_template,
+            exact_numbers,
+            ..
+        } = rendered;
+
+        // JSON is a subset of YAML, and much faster to parse.
+        match json::from_str(&val) {
+            Ok(Parsed(value)) => return Ok(Document::new(value, &val, exact_numbers)),
+            Err(error) if json_template => return Err(error.into()),
+            Err(_) => {}
+        }
+
+        let Parsed(value) = yaml::from_str(&val).map_err(|error| {
+            let location = error.location();
+            let origin =
+                location.and_then(|l| map.locate(&self.source, &val, l.index()));
+            let (span, value) = match origin {
+                Some((span, value)) => (Some(span), value),
+                None => (None, None),
+            };
+            Error::Yaml(Box::new(YamlError { error, span, value }))
+        })?;
+        Ok(Document::new(value, &val, exact_numbers))
+    }
+
+    /// List the placeholders in the template, in order.
+    pub fn placeholders(&self) -> Vec<PlaceholderInfo> {
+        let mut args_index = 0;
+        let mut stdin_index = 0;
+
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                Part::Literal(..) => None,
+                Part::Placeholder(placeholder) => Some(placeholder),
+            })
+            .map(|placeholder| {
+                let index = match placeholder.source {
+                    Source::Args => {
+                        args_index += 1;
+                        Some(args_index)
+                    }
+                    Source::Stdin => {
+                        stdin_index += 1;
+                        Some(stdin_index)
+                    }
+                    _ => None,
+                };
+                placeholder.info(index)
+            })
+            .collect()
+    }
+
+    /// Check that the template renders into valid YAML, without the real values.
+    ///
+    /// Each placeholder is substituted with a dummy value that suits its specifier,
+    /// e.g. `0` for `%d` or `"x"` for `%q`, and each expandable placeholder with a
+    /// single item or pair. No values, files, stdin or environment are read.
+    pub fn check(&self) -> Result<()> {
+        let mut out = Rendered::new(false, false, false, Sink::Buffer);
+
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(literal, start) => out.push_literal(literal, *start)?,
+                Part::Placeholder(placeholder) => {
+                    let Placeholder {
+                        specifier,
+                        expansion,
+                        ref span,
+                        ..
+                    } = *placeholder;
+                    let dummy = specifier.dummy();
+                    let source = ValueSource::Other;
+
+                    if expansion == Expansion::Pairs {
+                        out.push_value(Specifier::Quoted, "x", span, source)?;
+                        out.val.push(':');
+                    }
+                    out.push_value(specifier, dummy, span, source)?;
+                }
+            }
+        }
+
+        self.parse_rendered(out).map(|_| ())
+    }
+
+    /// Render the template into raw string using the fields of the given map or
+    /// struct as the named values.
+    pub fn render_values<T>(&self, values: &T) -> Result<String>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.render_with([], Options::default().values(values)?)
+    }
+
+    /// Render and format the template into JSON using the fields of the given map
+    /// or struct as the named values.
+    pub fn format_values<T>(&self, values: &T) -> Result<String>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.format_with([], Options::default().values(values)?)
+    }
+
+    /// Render and format the template into JSON.
+    pub fn format<'a, I>(&self, args: I) -> Result<String>
+    where
+        I: IntoIterator<Item = Cow<'a, str>>,
+    {
+        self.format_with(args, Options::default())
+    }
+
+    /// Render and format the template into JSON using the given options.
+    pub fn format_with<'a, I>(&self, args: I, options: Options) -> Result<String>
+    where
+        I: IntoIterator<Item = Cow<'a, str>>,
+    {
+        let document = self.render_yaml(args, options)?;
+        Ok(document.restore(json::to_string(&document.value)?, true))
+    }
+
+    /// Render and format the template into the writer as compact JSON.
+    ///
+    /// When the template is a single array expansion, e.g. `[%*-s]`, each value is
+    /// formatted and written as it is read, so that a large array does not need to
+    /// fit in memory. Any other template is formatted as a whole before writing.
+    /// On error, the output written so far is left incomplete.
+    pub fn format_to<'a, I, W>(
+        &self,
+        args: I,
+        options: Options,
+        mut writer: W,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = Cow<'a, str>>,
+        W: io::Write,
+    {
+        if !self.is_items_only() {
+            let document = self.render_yaml(args, options)?;
+            let json = document.restore(json::to_string(&document.value)?, true);
+            return Ok(writer.write_all(json.as_bytes())?);
+        }
+
+        writer.write_all(b"[")?;
+        self.render_mapped(args, options, Sink::Items(&mut writer, 0))?
+            .finish()?;
+        writer.write_all(b"]")?;
+        Ok(())
+    }
+
+    /// Whether the template is an array of the items of a single expansion, and
+    /// nothing else, e.g. `[%*-s]` or `[ %(name)*q ]`.
+    fn is_items_only(&self) -> bool {
+        match &self.parts[..] {
+            [Part::Literal(open, _), Part::Placeholder(placeholder), Part::Literal(close, _)] => {
+                open.trim() == "["
+                    && close.trim() == "]"
+                    && placeholder.expansion == Expansion::Items
+            }
+            _ => false,
+        }
+    }
+
+    /// Render and format the template into pretty JSON.
+    pub fn format_pretty<'a, I>(&self, args: I) -> Result<String>
+    where
+        I: IntoIterator<Item = Cow<'a, str>>,
+    {
+        self.format_pretty_with(args, Options::default())
+    }
+
+    /// Render and format the template into pretty JSON using the given options.
+    pub fn format_pretty_with<'a, I>(&self, args: I, options: Options) -> Result<String>
+    where
+        I: IntoIterator<Item = Cow<'a, str>>,
+    {
+        let document = self.render_yaml(args, options)?;
+        Ok(document.restore(json::to_string_pretty(&document.value)?, true))
+    }
+
+    /// Render and format the template into YAML.
+    pub fn format_yaml<'a, I>(&self, args: I) -> Result<String>
+    where
+        I: IntoIterator<Item = Cow<'a, str>>,
+    {
+        self.format_yaml_with(args, Options::default())
+    }
+
+    /// Render and format the template into YAML using the given options.
+    pub fn format_yaml_with<'a, I>(&self, args: I, options: Options) -> Result<String>
+    where
+        I: IntoIterator<Item = Cow<'a, str>>,
+    {
+        let document = self.render_yaml(args, options)?;
+        Ok(document.restore(yaml::to_string(&document.value)?, false))
+    }
+
+    /// Render and format the template into TOML.
+    pub fn format_toml<'a, I>(&self, args: I) -> Result<String>
+    where
+        I: IntoIterator<Item = Cow<'a, str>>,
+    {
+        self.format_toml_with(args, Options::default())
+    }
+
+    /// Render and format the template into TOML using the given options.
+    ///
+    /// The rendered value must be a table, and it must not contain null values,
+    /// arrays with mixed types, or keys that are not strings.
+    pub fn format_toml_with<'a, I>(&self, args: I, options: Options) -> Result<String>
+    where
+        I: IntoIterator<Item = Cow<'a, str>>,
+    {
+        // TOML can only represent the 64-bit integers and floats
+        let yaml = self.render_yaml(args, options.exact_numbers(false))?.value;
+        let toml = match to_toml(yaml, "") {
+            Ok(toml::Value::Table(table)) => Ok(table),
+            Ok(value) => Err(format!(
+                "the top-level value must be a table, found {}",
+                value.type_str()
+            )),
+            Err(e) => Err(e),
+        }
+        .map_err(toml::ser::Error::custom)?;
+        toml::to_string(&toml).map_err(Error::from)
+    }
+}
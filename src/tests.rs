@@ -1,7 +1,5 @@
 use crate as jf;
 use serde_json as json;
-use std::borrow::Cow;
-use std::io;
 
 #[test]
 fn test_format_positional() {
@@ -24,74 +22,635 @@ fn test_format_positional() {
 
 #[test]
 fn test_format_from_stdin() {
-    let mut chars = r#"{%q: %-s, %q: %-s, %q: %-s}"#.chars().enumerate();
+    let options = jf::Options::default().stdin("1\x002\x003".as_bytes());
+    let args = [r#"{%q: %-s, %q: %-s, %q: %-s}"#, "one", "two", "three"];
+    let res = jf::render_with(args.map(Into::into), options).unwrap();
+    assert_eq!(res, r#"{"one": 1, "two": 2, "three": 3}"#);
 
-    let mut stdin = ["1", "2", "3"]
-        .map(Into::into)
-        .map(io::Result::Ok)
-        .into_iter()
-        .enumerate();
+    let options = jf::Options::default().stdin("1\0true\0foo".as_bytes());
+    let args = [
+        r#"{"1": %-s, one: %q, "true": %s, truestr: %-q, foo: %-s, bar: %q, esc: "%%"}"#,
+        "1",
+        "true",
+        "bar",
+    ];
+    let res = jf::render_with(args.map(Into::into), options).unwrap();
+    assert_eq!(
+        res,
+        r#"{"1": 1, one: "1", "true": true, truestr: "true", foo: foo, bar: "bar", esc: "%"}"#
+    );
+}
 
-    let mut args = ["one", "two", "three"]
-        .map(Cow::from)
-        .into_iter()
-        .enumerate();
+#[test]
+fn test_format_expand_items_from_stdin() {
+    let options = jf::Options::default().stdin("1\0true\0foo".as_bytes());
+    let args = [r#"[start, %*-s, mid, %*s, end]"#, "2", "false", "bar"];
+    let res = jf::render_with(args.map(Into::into), options).unwrap();
+    assert_eq!(res, r#"[start, 1,true,foo, mid, 2,false,bar, end]"#);
+}
 
-    let (res, _) = jf::format_partial(&mut chars, &mut args, &mut stdin).unwrap();
-    assert_eq!(res, r#"{"one": 1, "two": 2, "three": 3}"#);
+#[test]
+fn test_format_expand_pairs_from_stdin() {
+    let options = jf::Options::default().stdin("one\x001\x00two\x002".as_bytes());
+    let args = [r#"{args: {%**q}, stdin: {%**-q}}"#, "three", "3"];
+    let res = jf::render_with(args.map(Into::into), options).unwrap();
+    assert_eq!(
+        res,
+        r#"{args: {"three":"3"}, stdin: {"one":"1","two":"2"}}"#
+    );
+}
 
-    let mut chars =
-        r#"{"1": %-s, one: %q, "true": %s, truestr: %-q, foo: %-s, bar: %q, esc: "%%"}"#
-            .chars()
-            .enumerate();
+#[test]
+fn test_format_stdin_delimiter() {
+    let render = |delimiter, stdin: &'static str| {
+        let options = jf::Options::default()
+            .stdin(stdin.as_bytes())
+            .delimiter(delimiter);
+        jf::format_with(["[%*-q]"].map(Into::into), options).unwrap()
+    };
+
+    assert_eq!(render(jf::Delimiter::Nul, "a\0b\0"), r#"["a","b"]"#);
+    assert_eq!(
+        render(jf::Delimiter::Newline, "a\r\nb\n\nc"),
+        r#"["a","b","","c"]"#
+    );
+    assert_eq!(
+        render(jf::Delimiter::Bytes(b"--".to_vec()), "a-b--c----"),
+        r#"["a-b","c",""]"#
+    );
+    assert_eq!(
+        render(jf::Delimiter::Bytes(b";".to_vec()), "a;b"),
+        r#"["a","b"]"#
+    );
+    assert_eq!(render(jf::Delimiter::Whole, "a\0b\n"), r#"["a\u0000b\n"]"#);
+    assert_eq!(render(jf::Delimiter::Whole, ""), "[]");
 
-    let mut stdin = ["1", "true", "foo"]
-        .map(Into::into)
-        .map(io::Result::Ok)
-        .into_iter()
-        .enumerate();
+    let delimiter = jf::cli::parse_delimiter(r"\t|\0").unwrap();
+    assert_eq!(delimiter, jf::Delimiter::Bytes(b"\t|\0".to_vec()));
+    let err = jf::cli::parse_delimiter(r"\x").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        r"jf: invalid escape in stdin delimiter '\x', use \0, \n, \r, \t or \\"
+    );
+}
 
-    let mut args = ["1", "true", "bar"].map(Cow::from).into_iter().enumerate();
+#[test]
+fn test_render_to() {
+    let render = |args: &[&'static str], stdin: &'static str| {
+        let options = jf::Options::default().stdin(stdin.as_bytes());
+        let mut output = vec![];
+        let args = args.iter().map(|a| (*a).into());
+        jf::render_to(args, options, &mut output).map(|()| output)
+    };
 
-    let (res, _) = jf::format_partial(&mut chars, &mut args, &mut stdin).unwrap();
     assert_eq!(
-        res,
-        r#"{"1": 1, one: "1", "true": true, truestr: "true", foo: foo, bar: "bar", esc: "%"}"#
+        render(&["[%*-s, %q]", "x"], "1\x002").unwrap(),
+        b"[1,2, \"x\"]"
+    );
+    assert_eq!(
+        render(&["{%**s, %(a?)q}", "a", "1"], "").unwrap(),
+        b"{\"a\":1, null}"
+    );
+    assert_eq!(render(&["[%*s, 1]"], "").unwrap(), b"[ 1]");
+
+    let err = render(&["[%*-d]"], "1\0x").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "jf: invalid value \"x\" for placeholder '%d' at column 4, expected an integer"
     );
 }
 
 #[test]
-fn test_format_expand_items_from_stdin() {
-    let mut chars = r#"[start, %*-s, mid, %*s, end]"#.chars().enumerate();
+fn test_format_to() {
+    let format = |args: &[&'static str], stdin: &'static str| {
+        let options = jf::Options::default().stdin(stdin.as_bytes());
+        let mut output = vec![];
+        let args = args.iter().map(|a| (*a).into());
+        jf::format_to(args, options, &mut output)
+            .map(|()| String::from_utf8(output).unwrap())
+    };
+
+    // Streamed item by item
+    assert_eq!(
+        format(&["[%*-s]"], "1\0a, b\0{x: [2]}").unwrap(),
+        r#"[1,"a","b",{"x":[2]}]"#
+    );
+    assert_eq!(format(&[" [ %*-q ]\n"], "1\x002").unwrap(), r#"["1","2"]"#);
+    assert_eq!(format(&["[%(a)*s]", "a=1", "a=2"], "").unwrap(), "[1,2]");
+    assert_eq!(format(&["[%*s]"], "").unwrap(), "[]");
 
-    let mut stdin = ["1", "true", "foo"]
-        .map(Into::into)
-        .map(io::Result::Ok)
-        .into_iter()
-        .enumerate();
+    // Formatted as a whole
+    assert_eq!(
+        format(&["{a: [%*-s]}"], "1\x002").unwrap(),
+        r#"{"a":[1,2]}"#
+    );
+    assert_eq!(format(&["[%*s, 3]", "1", "2"], "").unwrap(), "[1,2,3]");
 
-    let mut args = ["2", "false", "bar"].map(Cow::from).into_iter().enumerate();
+    let err = format(&["[%*-s]"], "1\0[").unwrap_err();
+    assert!(err.to_string().starts_with(
+        "yaml: stdin value no. 2 substituted at '%*-s' (column 4) produced invalid YAML"
+    ));
+    assert_eq!(err.placeholder(), Some("%*-s"));
+}
 
-    let (res, _) = jf::format_partial(&mut chars, &mut args, &mut stdin).unwrap();
-    assert_eq!(res, r#"[start, 1,true,foo, mid, 2,false,bar, end]"#);
+#[test]
+fn test_json_template() {
+    let format = |template: &str, args: &[&'static str], json_template| {
+        let template = jf::Template::parse(template).unwrap();
+        let options = jf::Options::default()
+            .no_stdin()
+            .json_template(json_template);
+        template.format_with(args.iter().map(|a| (*a).into()), options)
+    };
+
+    // Parsed as JSON, keeping the keys in order
+    let json = r#"{"b": %q, "c": %s, "a": [%*s]}"#;
+    let args = ["x", "1000.0", "1", "2.0"];
+    assert_eq!(
+        format(json, &args, false).unwrap(),
+        r#"{"b":"x","c":1000.0,"a":[1,2.0]}"#
+    );
+    assert_eq!(
+        format(json, &args, true).unwrap(),
+        format(json, &args, false).unwrap()
+    );
+
+    // Not JSON, parsed as YAML, unless JSON is expected
+    assert_eq!(
+        format("{b: %q, a: %s}", &["x", "y"], false).unwrap(),
+        r#"{"b":"x","a":"y"}"#
+    );
+    let err = format("{b: %q}", &["x"], true).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "json: key must be a string at line 1 column 2"
+    );
+    assert_eq!(err.returncode(), 2);
+
+    // The duplicate keys are rejected as in YAML
+    let dup = r#"{"a": %s, "a": %s}"#;
+    let err = format(dup, &["1", "2"], false).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("yaml: duplicate entry with key \"a\""));
+    let err = format(dup, &["1", "2"], true).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("json: duplicate entry with key \"a\""));
+
+    // The streamed items too
+    let items = |stdin: &'static str, json_template| {
+        let options = jf::Options::default()
+            .stdin(stdin.as_bytes())
+            .json_template(json_template);
+        let mut output = vec![];
+        jf::format_to(["[%*-s]"].map(Into::into), options, &mut output)
+            .map(|()| String::from_utf8(output).unwrap())
+    };
+    assert_eq!(
+        items("1\x00\"a\"\x00{\"b\": []}", true).unwrap(),
+        r#"[1,"a",{"b":[]}]"#
+    );
+    assert_eq!(items("1\x00a", false).unwrap(), r#"[1,"a"]"#);
+    assert!(items("1\x00a", true).is_err());
 }
 
 #[test]
-fn test_format_expand_pairs_from_stdin() {
-    let mut chars = r#"{args: {%**q}, stdin: {%**-q}}"#.chars().enumerate();
+#[cfg(feature = "preserve_order")]
+fn test_preserve_order() {
+    let template = jf::Template::parse("{a: %j, z: %(z)s, m: [%(m)*s]}").unwrap();
+    let values = json::json!({"z": {"y": 1, "x": 2}, "m": [{"b": 1, "a": 2}]});
+    let options = jf::Options::default().no_stdin().values(&values).unwrap();
+    assert_eq!(
+        template
+            .format_with([r#"{"d": 1, "c": 2}"#.into()], options)
+            .unwrap(),
+        r#"{"a":{"d":1,"c":2},"z":{"y":1,"x":2},"m":[{"b":1,"a":2}]}"#
+    );
 
-    let mut stdin = ["one", "1", "two", "2"]
-        .map(Into::into)
-        .map(io::Result::Ok)
-        .into_iter()
-        .enumerate();
+    let template = jf::Template::parse("{z: %(z)s, a: 1}").unwrap();
+    let options = jf::Options::default().no_stdin().values(&values).unwrap();
+    assert_eq!(
+        template.format_toml_with([], options).unwrap(),
+        "a = 1\n\n[z]\ny = 1\nx = 2\n"
+    );
+}
 
-    let mut args = ["three", "3"].map(Cow::from).into_iter().enumerate();
+#[test]
+fn test_exact_numbers() {
+    let big = "12345678901234567890.123";
+    let int = "123456789012345678901234";
+    let args = || [big, int, "-0.10", "1E5"].map(Into::into);
+    let exact = || jf::Options::default().no_stdin().exact_numbers(true);
+
+    // Rendered into JSON, or into YAML, along with a string like a kept number
+    for template in [
+        r#"{"a": %s, "b": [%j, %f, %s, "jf-number-0"]}"#,
+        "{a: %s, b: [%j, %f, %s, jf-number-0]}",
+    ] {
+        let template = jf::Template::parse(template).unwrap();
+        assert_eq!(
+            template.format_with(args(), exact()).unwrap(),
+            format!(r#"{{"a":{big},"b":[{int},-0.10,1E5,"jf-number-0"]}}"#)
+        );
+        assert_eq!(
+            template.format_pretty_with(args(), exact()).unwrap(),
+            format!("{{\n  \"a\": {big},\n  \"b\": [\n    {int},\n    -0.10,\n    1E5,\n    \"jf-number-0\"\n  ]\n}}")
+        );
+        assert_eq!(
+            template.format_yaml_with(args(), exact()).unwrap(),
+            format!("a: {big}\nb:\n- {int}\n- -0.10\n- 1E5\n- jf-number-0\n")
+        );
 
-    let (res, _) = jf::format_partial(&mut chars, &mut args, &mut stdin).unwrap();
+        // Formatted as 64-bit integers and floats otherwise, e.g. without the
+        // digits that a float cannot hold
+        let args = ["1.000000000000000000001", "2.50", "-0.10", "1E5"].map(Into::into);
+        let options = jf::Options::default().no_stdin();
+        assert_eq!(
+            template.format_with(args, options).unwrap(),
+            r#"{"a":1.0,"b":[2.5,-0.1,100000.0,"jf-number-0"]}"#
+        );
+    }
+
+    // Always formatted in TOML, and only kept when valid in JSON
+    let template = jf::Template::parse("{a: %s, b: 0x1F, c: .5}").unwrap();
     assert_eq!(
-        res,
-        r#"{args: {"three":"3"}, stdin: {"one":"1","two":"2"}}"#
+        template
+            .format_toml_with(["1.000000000000000000001".into()], exact())
+            .unwrap(),
+        "a = 1.0\nb = 31\nc = 0.5\n"
+    );
+    assert_eq!(
+        template.format_with([big.into()], exact()).unwrap(),
+        format!(r#"{{"a":{big},"b":31,"c":0.5}}"#)
+    );
+
+    // The duplicate keys are still rejected
+    let err = jf::Template::parse("{a: 1.50, a: 2}")
+        .unwrap()
+        .format_with([], exact())
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("yaml: duplicate entry with key \"a\""));
+
+    // The streamed items too
+    let stdin = format!("{big}\x00{int}\x001.50, [2.50]");
+    let options = jf::Options::default()
+        .stdin(stdin.as_bytes())
+        .exact_numbers(true);
+    let mut output = vec![];
+    jf::format_to(["[%*-s]"].map(Into::into), options, &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        format!("[{big},{int},1.50,[2.50]]")
+    );
+}
+
+#[test]
+#[cfg(feature = "arbitrary_precision")]
+fn test_arbitrary_precision() {
+    // The numbers of the named values are kept as written too
+    let big = "12345678901234567890.123";
+    let values = json::json!({"n": json::from_str::<json::Value>(big).unwrap()});
+    for template in [r#"{"n": %(n)s, "m": [%(n)*s]}"#, "{n: %(n)s, m: [%(n)*s]}"] {
+        let options = jf::Options::default()
+            .no_stdin()
+            .exact_numbers(true)
+            .values(&values)
+            .unwrap();
+        assert_eq!(
+            jf::Template::parse(template)
+                .unwrap()
+                .format_with([], options)
+                .unwrap(),
+            format!(r#"{{"n":{big},"m":[{big}]}}"#)
+        );
+    }
+}
+
+#[test]
+fn test_format_stdin_csv() {
+    let csv = |header| jf::Delimiter::Csv {
+        delimiter: b',',
+        header,
+    };
+    let format = |template: &str, delimiter, stdin: &'static str| {
+        let options = jf::Options::default()
+            .stdin(stdin.as_bytes())
+            .delimiter(delimiter);
+        jf::format_with([template].map(Into::into), options).unwrap()
+    };
+
+    assert_eq!(
+        format("[%*-q]", csv(false), "a,\"b,\"\"c\"\"\"\nd\n"),
+        r#"["a","b,\"c\"","d"]"#
+    );
+    assert_eq!(
+        format("{%**-s}", csv(false), "a,1\nb,2\n"),
+        r#"{"a":1,"b":2}"#
+    );
+
+    let table = "name,age\nfoo,1\nbar,2\n";
+    assert_eq!(
+        format("[%*-s]", csv(true), table),
+        r#"[{"name":"foo","age":"1"},{"name":"bar","age":"2"}]"#
+    );
+    assert_eq!(
+        format("{first: %(name)q, names: [%(name)*q]}", csv(true), table),
+        r#"{"first":"foo","names":["foo","bar"]}"#
+    );
+
+    // A short record is padded with empty fields, a long one is an error
+    let table = "a,b\n1\n2,3\n";
+    assert_eq!(
+        format("{a: %(a)q, b: %(b)q, bs: [%(b)*q]}", csv(true), table),
+        r#"{"a":"1","b":"","bs":["","3"]}"#
+    );
+    assert_eq!(
+        format("[%*-s]", csv(true), table),
+        r#"[{"a":"1","b":""},{"a":"2","b":"3"}]"#
+    );
+    let options = jf::Options::default()
+        .stdin("a,b\n1,2,3\n".as_bytes())
+        .delimiter(csv(true));
+    let err = jf::format_with(["[%*-s]"].map(Into::into), options).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "io: CSV record 2 has 3 fields, but the header has 2"
+    );
+
+    // An invalid record is an error, not the end of the values
+    for template in ["[%*-q]", "{%**-q}"] {
+        let options = jf::Options::default()
+            .stdin(&b"a,b\n\xff,c\nd,e\n"[..])
+            .delimiter(csv(false));
+        let err = jf::format_with([template].map(Into::into), options).unwrap_err();
+        assert!(err.to_string().starts_with("io: CSV parse error: record 1"));
+    }
+
+    let tsv = jf::Delimiter::Csv {
+        delimiter: b'\t',
+        header: true,
+    };
+    let options = jf::Options::default()
+        .stdin("name\tage\nfoo\t1\n".as_bytes())
+        .delimiter(tsv);
+    let args = ["{name: %(name)q, age: %(age)d}", "name=bar"];
+    assert_eq!(
+        jf::format_with(args.map(Into::into), options).unwrap(),
+        r#"{"name":"bar","age":1}"#
+    );
+}
+
+#[test]
+fn test_format_with_no_stdin() {
+    let options = jf::Options::default().no_stdin();
+    let res = jf::format_with(["[%*-s]"].map(Into::into), options).unwrap();
+    assert_eq!(res, "[]");
+
+    let options = jf::Options::default().no_stdin();
+    let err = jf::format_with(["[%-s]"].map(Into::into), options).unwrap_err();
+    assert_eq!(err.to_string(), "jf: placeholder missing value at column 3");
+}
+
+#[test]
+fn test_format_values() {
+    let template = jf::Template::parse(
+        "{name: %(name)q, tags: [%(tags)*q], all: %(tags)s, meta: {%(meta)**s}, n: %(n)q, none: %(none?)s}",
+    )
+    .unwrap();
+
+    let values = json::json!({
+        "name": "foo",
+        "tags": ["a", "b"],
+        "meta": {"k": 1},
+        "n": 1,
+        "none": null,
+    });
+
+    assert_eq!(
+        template.format_values(&values).unwrap(),
+        r#"{"name":"foo","tags":["a","b"],"all":["a","b"],"meta":{"k":1},"n":"1","none":null}"#
+    );
+
+    let values = std::collections::HashMap::from([("name", "bar")]);
+    let template = jf::Template::parse("%(name)q").unwrap();
+    assert_eq!(template.render_values(&values).unwrap(), r#""bar""#);
+}
+
+#[test]
+fn test_format_values_with_args() {
+    let template = jf::Template::parse("[%(foo)q, %(bar)q]").unwrap();
+    let options = jf::Options::default()
+        .values(&json::json!({"foo": "foo", "bar": "bar"}))
+        .unwrap();
+
+    assert_eq!(
+        template
+            .format_with(["bar=baz"].map(Into::into), options)
+            .unwrap(),
+        r#"["foo","baz"]"#
+    );
+
+    let err = jf::Options::default().values(&["foo"]).err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "jf: named values must be a map of names to values"
+    );
+}
+
+#[test]
+fn test_format_env() {
+    let template = jf::Template::parse(
+        "{home: %($HOME)q, branch: %(BRANCH$)q, ci: %($CI=false)b, tag: %($TAG?)q, opt: %(OPT$)?q %s}",
+    )
+    .unwrap();
+
+    let env = [("HOME", "/home/foo"), ("BRANCH", "main")];
+    let options = jf::Options::default().env(env);
+    assert_eq!(
+        template
+            .format_with(["1"].map(Into::into), options)
+            .unwrap(),
+        r#"{"home":"/home/foo","branch":"main","ci":false,"tag":null,"opt":1}"#
+    );
+
+    let options = jf::Options::default().env([("HOME", "/")]);
+    let err = template
+        .format_with(["1"].map(Into::into), options)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "jf: no value for placeholder '%($BRANCH)q' at column 36"
+    );
+
+    // Environment variables are not named values
+    let template = jf::Template::parse("[%(foo)q, %($foo)q]").unwrap();
+    let options = jf::Options::default().env([("foo", "env")]);
+    assert_eq!(
+        template
+            .format_with(["foo=arg"].map(Into::into), options)
+            .unwrap(),
+        r#"["arg","env"]"#
+    );
+
+    let args = ["%($PATH)q"].map(Into::into);
+    assert_eq!(
+        jf::format(args).unwrap(),
+        jf::json::to_string(&std::env::var("PATH").unwrap()).unwrap()
+    );
+}
+
+#[test]
+fn test_env_placeholder_error() {
+    let err = jf::Template::parse("%($foo)*q").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "jf: environment variable placeholder 'foo' at column 8 cannot be expanded"
+    );
+
+    let err = jf::Template::parse("%(foo$bar)q").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "jf: environment variable placeholder 'foo' at column 6 must have '$' either before or after the name"
+    );
+
+    let err = jf::Template::parse("%($foo$)q").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "jf: invalid character '$' in placeholder name at column 6, use numbers, letters and underscores only"
+    );
+}
+
+#[test]
+fn test_format_prompt() {
+    let template =
+        jf::Template::parse("{a: %(a)d, b: %(b=x)q, c: %(c)q, d: %(a)d, e: [%(e)*q]}")
+            .unwrap();
+
+    let mut asked = vec![];
+    let options = jf::Options::default().prompt(|placeholder, _| {
+        asked.push(placeholder.name.clone().unwrap());
+        Ok(None)
+    });
+    assert_eq!(
+        template
+            .format_with(["a=2", "c=bar"].map(Into::into), options)
+            .unwrap(),
+        r#"{"a":2,"b":"x","c":"bar","d":2,"e":[]}"#
+    );
+    assert_eq!(asked, ["b"]);
+
+    let mut answers = ["x", "1", "", "foo"].into_iter();
+    let mut asked = vec![];
+    let options = jf::Options::default().prompt(|placeholder, error| {
+        let name = placeholder.name.clone().unwrap();
+        asked.push((name, error.map(ToString::to_string)));
+        Ok(answers.next().map(String::from))
+    });
+    assert_eq!(
+        template.format_with([], options).unwrap(),
+        r#"{"a":1,"b":"","c":"foo","d":1,"e":[]}"#
+    );
+    let error =
+        "jf: invalid value \"x\" for placeholder '%d' at column 8, expected an integer";
+    assert_eq!(
+        asked,
+        [
+            ("a".into(), None),
+            ("a".into(), Some(error.into())),
+            ("b".into(), None),
+            ("c".into(), None),
+        ]
+    );
+}
+
+#[test]
+fn test_format_nested_names() {
+    let args = [
+        "{user: %(user)s, city: %(user.address.city)q, id: %(items[1].id)d, ids: [%(items)*s]}",
+        "user.name=foo",
+        "user.address.city=bar",
+        "items[0].id=1",
+        "items[1].id=2",
+    ];
+    // The nested keys are sorted, unless the order is preserved
+    let user = if cfg!(feature = "preserve_order") {
+        r#"{"name":"foo","address":{"city":"bar"}}"#
+    } else {
+        r#"{"address":{"city":"bar"},"name":"foo"}"#
+    };
+    assert_eq!(
+        jf::format(args.map(Into::into)).unwrap(),
+        format!(
+            r#"{{"user":{user},"city":"bar","id":2,"ids":[{{"id":"1"}},{{"id":"2"}}]}}"#
+        )
+    );
+
+    let args = ["[%(a[1])s, %(a[2].b?)s, %(a.b=x)q]", "a[1]=1"];
+    assert_eq!(jf::format(args.map(Into::into)).unwrap(), r#"[1,null,"x"]"#);
+
+    // Nested values passed as arguments are merged into the values from options
+    let template = jf::Template::parse("%(user)s").unwrap();
+    let options = jf::Options::default()
+        .values(&json::json!({"user": {"age": 30, "name": "foo"}}))
+        .unwrap();
+    assert_eq!(
+        template
+            .format_with(["user.name=bar"].map(Into::into), options)
+            .unwrap(),
+        r#"{"age":30,"name":"bar"}"#
+    );
+
+    let template = jf::Template::parse("[%(tags[0])q, %(user.tags)*q]").unwrap();
+    let values = json::json!({"tags": ["a"], "user": {"tags": ["b", "c"]}});
+    assert_eq!(template.format_values(&values).unwrap(), r#"["a","b","c"]"#);
+}
+
+#[test]
+fn test_nested_name_error() {
+    let args = ["%(a.b)s", "a=1", "a.b=2"];
+    let err = jf::format(args.map(Into::into)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "jf: invalid name 'a.b' for value no. 2, it conflicts with the other values"
+    );
+
+    let args = ["%(a)s", "a[0]=1", "a.b=2"];
+    let err = jf::format(args.map(Into::into)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "jf: invalid name 'a.b' for value no. 2, it conflicts with the other values"
+    );
+
+    let args = ["%(a)s", "a.b=2", "a=1"];
+    let err = jf::format(args.map(Into::into)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "jf: invalid name 'a' for value no. 2, it conflicts with the other values"
+    );
+
+    let args = ["%(a)s", "a[65536]=1"];
+    let err = jf::format(args.map(Into::into)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "jf: invalid name 'a[65536]' for value no. 1, the index must not be larger than 65535"
+    );
+
+    for name in ["a..b", ".a", "a.", "a[]", "a[x]", "a[0", "a]0[", "[0]"] {
+        let err = jf::Template::parse(&format!("%({name})s")).unwrap_err();
+        let col = name.len() + 3;
+        assert_eq!(
+            err.to_string(),
+            format!("jf: invalid placeholder name '{name}' at column {col}, use names like 'foo', 'foo.bar' or 'foo[0]'")
+        );
+    }
+
+    let err = jf::Template::parse("%($a.b)s").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "jf: environment variable placeholder 'a.b' at column 7 cannot have a nested name"
     );
 }
 
@@ -170,16 +729,9 @@ fn test_format_named_from_file_path() {
 
 #[test]
 fn test_format_named_from_stdin() {
-    let mut chars = "{%(FOO)q: %(BAR)q}".chars().enumerate();
-    let mut stdin = ["foo", "bar"]
-        .map(Into::into)
-        .map(io::Result::Ok)
-        .into_iter()
-        .enumerate();
-    let mut args = ["FOO@-", "BAR@-"].map(Cow::from).into_iter().enumerate();
-
-    let (res, _) = jf::format_partial(&mut chars, &mut args, &mut stdin).unwrap();
-
+    let options = jf::Options::default().stdin("foo\0bar".as_bytes());
+    let args = ["{%(FOO)q: %(BAR)q}", "FOO@-", "BAR@-"];
+    let res = jf::render_with(args.map(Into::into), options).unwrap();
     assert_eq!(res, r#"{"foo": "bar"}"#);
 }
 
@@ -210,36 +762,32 @@ fn test_format_named_with_default_from_file() {
 
 #[test]
 fn test_format_named_with_default_from_stdin() {
-    let mut chars = "%(foo@-)q".chars().enumerate();
-    let mut args = [].into_iter().enumerate();
-    let mut stdin = ["foo"]
-        .map(Into::into)
-        .map(io::Result::Ok)
-        .into_iter()
-        .enumerate();
+    let template = jf::Template::parse("%(foo@-)q").unwrap();
 
-    let (res, _) = jf::format_partial(&mut chars, &mut args, &mut stdin).unwrap();
+    let options = jf::Options::default().stdin("foo".as_bytes());
+    let res = template.render_with([], options).unwrap();
     assert_eq!(res, r#""foo""#);
 
-    let mut chars = "%(foo@-)q".chars().enumerate();
-    let mut args = ["foo=bar"].map(Into::into).into_iter().enumerate();
-
-    let mut stdin = ["foo"]
-        .map(Into::into)
-        .map(io::Result::Ok)
-        .into_iter()
-        .enumerate();
-    let (res, _) = jf::format_partial(&mut chars, &mut args, &mut stdin).unwrap();
+    let options = jf::Options::default().stdin("foo".as_bytes());
+    let res = template
+        .render_with(["foo=bar"].map(Into::into), options)
+        .unwrap();
     assert_eq!(res, r#""bar""#);
 }
 
 #[test]
 fn test_unexpected_eof() {
-    let mut chars = "%(foo@-)q".chars().enumerate();
-    let mut args = [].into_iter().enumerate();
-    let mut stdin = [].into_iter().enumerate();
+    let template = jf::Template::parse("%(foo@-)q").unwrap();
+
+    let err = template
+        .render_with([], jf::Options::default().no_stdin())
+        .unwrap_err()
+        .to_string();
+
+    assert_eq!(err, "io: unexpected end of input");
 
-    let err = jf::format_partial(&mut chars, &mut args, &mut stdin)
+    let err = template
+        .render_with(["foo@-"].map(Into::into), jf::Options::default().no_stdin())
         .unwrap_err()
         .to_string();
 
@@ -333,6 +881,183 @@ fn test_format_named_pairs() {
     );
 }
 
+#[test]
+fn test_format_validated() {
+    let args = ["{int: %d, float: %f, bool: %b}", " -1", "1.5e3", "True"];
+    assert_eq!(
+        jf::format(args.map(Into::into)).unwrap(),
+        r#"{"int":-1,"float":1500.0,"bool":true}"#
+    );
+
+    let args = ["[%*d]", "1", "2"].map(Into::into);
+    assert_eq!(jf::format(args).unwrap(), "[1,2]");
+
+    let args = ["{%**b}", "a", "false"].map(Into::into);
+    assert_eq!(jf::format(args).unwrap(), r#"{"a":false}"#);
+
+    let args = [
+        "[%(a)d, %(b=0.5)f, %(c)b, %(d)*d]",
+        "a=1",
+        "c=false",
+        "d=2",
+        "d=3",
+    ]
+    .map(Into::into);
+    assert_eq!(jf::format(args).unwrap(), "[1,0.5,false,2,3]");
+
+    let options = jf::Options::default().stdin("7\x00true".as_bytes());
+    let res = jf::format_with(["[%-d, %*-b]"].map(Into::into), options).unwrap();
+    assert_eq!(res, "[7,true]");
+
+    // Floats that are not JSON numbers are written as JSON numbers
+    let args = ["[%*f]", ".5", "5.", "+1", "-.5", "1E5", "01.5", "0.10"].map(Into::into);
+    assert_eq!(jf::render(args).unwrap(), "[0.5,5.0,1.0,-0.5,1E5,1.5,0.10]");
+    let args = [r#"{"a": %f}"#, ".5"].map(Into::into);
+    let options = jf::Options::default().json_template(true);
+    assert_eq!(jf::format_with(args, options).unwrap(), r#"{"a":0.5}"#);
+}
+
+#[test]
+fn test_invalid_validated_value_error() {
+    let args = ["{a: %d}", "1; evil: true"].map(Into::into);
+    assert_eq!(
+        jf::format(args).unwrap_err().to_string(),
+        r#"jf: invalid value "1; evil: true" for placeholder '%d' at column 5, expected an integer"#
+    );
+
+    let args = ["[%*f]", "1", "inf"].map(Into::into);
+    assert_eq!(
+        jf::format(args).unwrap_err().to_string(),
+        r#"jf: invalid value "inf" for placeholder '%f' at column 3, expected a finite number"#
+    );
+
+    let args = ["{%(a)**b}", "a=x", "a=yes"].map(Into::into);
+    assert_eq!(
+        jf::format(args).unwrap_err().to_string(),
+        r#"jf: invalid value "yes" for placeholder '%b' at column 7, expected a boolean"#
+    );
+}
+
+#[test]
+fn test_format_json() {
+    let args = ["{a: %j, b: [%*j]}", r#" {"x": [1, "5"]} "#, "null", "\"c\""];
+    assert_eq!(
+        jf::render(args.map(Into::into)).unwrap(),
+        r#"{a: {"x":[1,"5"]}, b: [null,"c"]}"#
+    );
+
+    let args = ["{a: %(a)j}", r#"a={"x": 1}, "evil": 2"#];
+    assert_eq!(
+        jf::format(args.map(Into::into)).unwrap_err().to_string(),
+        r#"jf: invalid value "{\"x\": 1}, \"evil\": 2" for placeholder '%j' at column 8, expected a single JSON value"#
+    );
+
+    let args = ["%j", "foo"].map(Into::into);
+    assert_eq!(
+        jf::format(args).unwrap_err().to_string(),
+        r#"jf: invalid value "foo" for placeholder '%j' at column 1, expected a single JSON value"#
+    );
+
+    // The keys are kept in order, and the strings and numbers as written
+    let args = ["{a: %j}", r#"{"b": 1, "a": [2.50, "x \" y"]}"#].map(Into::into);
+    assert_eq!(
+        jf::format(args).unwrap(),
+        r#"{"a":{"b":1,"a":[2.5,"x \" y"]}}"#
+    );
+    let args = ["{a: %j}", r#"{"b": 1, "a": [2.50, "x \" y"]}"#].map(Into::into);
+    assert_eq!(
+        jf::render(args).unwrap(),
+        r#"{a: {"b":1,"a":[2.50,"x \" y"]}}"#
+    );
+
+    let args = ["{a: %j}", r#"{"a": 1, "a": 2}"#].map(Into::into);
+    assert_eq!(
+        jf::format(args).unwrap_err().to_string(),
+        r#"jf: invalid value "{\"a\": 1, \"a\": 2}" for placeholder '%j' at column 5, expected a single JSON value"#
+    );
+}
+
+#[test]
+fn test_format_strict() {
+    let template =
+        jf::Template::parse("{a: %s, b: [%*-s], c: %(c)s, d: %(d)q}").unwrap();
+    let strict = |stdin: &'static str| {
+        jf::Options::default().stdin(stdin.as_bytes()).strict(true)
+    };
+
+    let args = ["1", "c=-1.5", "d=g: h"].map(Into::into);
+    let options = strict("x y\x00[1, 2]\x00{e: f}");
+    assert_eq!(
+        template.format_with(args, options).unwrap(),
+        r#"{"a":1,"b":["x y",[1,2],{"e":"f"}],"c":-1.5,"d":"g: h"}"#
+    );
+
+    for value in [
+        "1, evil: true",
+        "x: y",
+        "[1",
+        "1]",
+        "1 # comment",
+        "{x: 1}: y",
+        "1\n  evil: true",
+        "",
+    ] {
+        let err = template
+            .format_with([value].map(Into::into), strict(""))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("jf: invalid value {value:?} for placeholder '%s' at column 5, expected a single YAML value")
+        );
+    }
+
+    let err = template
+        .format_with(["1"].map(Into::into), strict("1\x002, 3"))
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        r#"jf: invalid value "2, 3" for placeholder '%s' at column 15, expected a single YAML value"#
+    );
+
+    let quoted = jf::Template::parse(r#"{a: "%s", b: '%s', c: it's %s}"#).unwrap();
+    let args = [r#"x \" y: z"#, "it''s [x", "1"].map(Into::into);
+    assert_eq!(
+        quoted.format_with(args, strict("")).unwrap(),
+        r#"{"a":"x \" y: z","b":"it's [x","c":"it's 1"}"#
+    );
+
+    for (quoted, value) in [
+        (r#"{a: "%s"}"#, r#"", b: ""#),
+        ("{a: '%s'}", "', b: '"),
+        (r#"{a: "%s"}"#, r"x\"),
+        ("{a: '%s'}", "x' y"),
+    ] {
+        let err = jf::Template::parse(quoted)
+            .unwrap()
+            .format_with([value].map(Into::into), strict(""))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("jf: invalid value {value:?} for placeholder '%s' at column 6, expected a value that stays inside the quotes")
+        );
+    }
+
+    let err = template
+        .format_with(["1", "c=a: b"].map(Into::into), strict(""))
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        r#"jf: invalid value "a: b" for placeholder '%s' at column 26, expected a single YAML value"#
+    );
+
+    let args = ["1, evil: true", "c=1", "d=1"].map(Into::into);
+    let options = jf::Options::default().no_stdin();
+    assert_eq!(
+        template.format_with(args, options).unwrap(),
+        r#"{"a":1,"evil":true,"b":[],"c":1,"d":"1"}"#
+    );
+}
+
 #[test]
 fn test_render() {
     let args = ["%*s", "1", "2", "3"].map(Into::into);
@@ -342,12 +1067,449 @@ fn test_render() {
     assert_eq!(jf::render(args).unwrap(), r#"1   "2", (3)"#);
 }
 
+#[test]
+fn test_template_render_multiple_times() {
+    let template = jf::Template::parse("{%q: %(value=default)q}").unwrap();
+
+    assert_eq!(
+        template.format(["foo"].map(Into::into)).unwrap(),
+        r#"{"foo":"default"}"#
+    );
+
+    assert_eq!(
+        template
+            .format(["bar", "value=baz"].map(Into::into))
+            .unwrap(),
+        r#"{"bar":"baz"}"#
+    );
+}
+
+#[test]
+fn test_template_parse_error() {
+    assert_eq!(
+        jf::Template::parse("{foo: %(foo)q, bar: %z}")
+            .unwrap_err()
+            .to_string(),
+        "jf: invalid placeholder '%z' at column 21, use one of '%s', '%q', '%d', '%f', '%b' or '%j', or escape it using '%%'"
+    );
+}
+
+#[test]
+fn test_template_error() {
+    let err = jf::Template::parse("{a: %q,\n é: %(foo=x)?s}").unwrap_err();
+    let jf::Error::Template(err) = err else {
+        panic!("expected a template error, got {err:?}");
+    };
+    assert_eq!(
+        *err,
+        jf::TemplateError::OptionalWithDefault {
+            name: "foo".into(),
+            span: jf::Span {
+                text: "%(foo=x)?".into(),
+                chars: 12..21,
+                bytes: 13..22,
+                offset: 20,
+                line: 2,
+                column: 13,
+            },
+        }
+    );
+
+    let template = jf::Template::parse("[%s, %(a)q]").unwrap();
+    let err = template.render(["1"].map(Into::into)).unwrap_err();
+    let jf::Error::Template(err) = err else {
+        panic!("expected a template error, got {err:?}");
+    };
+    assert_eq!(
+        err.span().map(|s| (s.text.as_str(), s.offset)),
+        Some(("%(a)q", 9))
+    );
+    assert!(matches!(*err, jf::TemplateError::MissingNamedValue { .. }));
+
+    let err = template.render(["1", "2"].map(Into::into)).unwrap_err();
+    assert!(matches!(err, jf::Error::Template(ref e) if e.span().is_none()));
+    assert_eq!(err.returncode(), 1);
+}
+
+#[test]
+fn test_validate() {
+    let template =
+        "{a: %(a=1)?q, b: %z, c: %(c)q, %s, d: %(d?)?q, e: %(e=x)**s, f: %()s, %(g";
+    let messages: Vec<_> = jf::Template::validate(template)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "optional placeholder 'a' at column 10 cannot have a default value",
+            "invalid placeholder '%z' at column 18, use one of '%s', '%q', '%d', '%f', '%b' or '%j', or escape it using '%%'",
+            "positional placeholder '%s' at column 32 was used after named placeholders, use named placeholder syntax '%(NAME)s' instead",
+            "optional placeholder 'd' at column 43 cannot also be nullable",
+            "expandable placeholder 'e' at column 58 cannot have a default value",
+            "placeholder missing name at column 67",
+            "template ended with incomplete placeholder",
+        ]
+    );
+
+    // The first error is the one returned by `parse`
+    let err = jf::Template::parse(template).unwrap_err();
+    assert_eq!(err.to_string(), format!("jf: {}", messages[0]));
+
+    assert!(jf::Template::validate("{a: %s, b: %(b)q}").is_empty());
+}
+
+#[test]
+fn test_check() {
+    let template = "{a: %s, b: [%*q, %*-d], c: {%(c)**b}, d: %(d@/nope)j, e: %($E)f}";
+    jf::Template::parse(template).unwrap().check().unwrap();
+
+    let err = jf::Template::parse("{a: %s b: %q}")
+        .unwrap()
+        .check()
+        .unwrap_err();
+    assert_eq!(err.returncode(), 3);
+    let span = err.span().unwrap();
+    assert_eq!((span.line, span.column), (1, 9));
+
+    let err = jf::Template::parse("{a: %(a)q %(b)q}")
+        .unwrap()
+        .check()
+        .unwrap_err();
+    assert!(err.to_string().starts_with(
+        "yaml: value substituted at '%(b)q' (column 14) produced invalid YAML"
+    ));
+}
+
+#[test]
+fn test_placeholders() {
+    let template = jf::Template::parse(
+        "{a: %s, b: [%*-q, %q], c: %(c=1)d, d: %(d@-)s, e: %($E?)b}",
+    )
+    .unwrap();
+    let placeholders = template.placeholders();
+
+    let summary: Vec<_> = placeholders
+        .iter()
+        .map(|p| (p.span.text.as_str(), p.input, p.index, p.name.as_deref()))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("%s", "args", Some(1), None),
+            ("%*-q", "stdin", Some(1), None),
+            ("%q", "args", Some(2), None),
+            ("%(c=1)d", "args", None, Some("c")),
+            ("%(d@-)s", "args", None, Some("d")),
+            ("%($E?)b", "env", None, Some("E")),
+        ]
+    );
+    assert_eq!(placeholders[1].expansion, "*");
+    assert_eq!(placeholders[3].default.as_deref(), Some("1"));
+    assert_eq!(placeholders[4].default_file.as_deref(), Some("-"));
+    assert!(placeholders[5].nullable && !placeholders[5].optional);
+
+    let template = jf::Template::parse("[%s, %(foo)?*q]").unwrap();
+    assert_eq!(
+        jf::cli::placeholders_table(&template.placeholders()),
+        "PLACEHOLDER  INPUT  INDEX/NAME  SPECIFIER  EXPANSION  DEFAULT  NULLABLE  OPTIONAL
+%s           args   1           s          -          -        no        no
+%(foo)?*q    args   foo         q          *          -        no        yes"
+    );
+
+    let value: json::Value =
+        json::from_str(&jf::cli::placeholders_json(&template.placeholders())).unwrap();
+    assert_eq!(
+        value[1],
+        json::json!({
+            "placeholder": "%(foo)?*q",
+            "line": 1,
+            "column": 14,
+            "input": "args",
+            "index": null,
+            "name": "foo",
+            "specifier": "q",
+            "expansion": "*",
+            "default": null,
+            "default_file": null,
+            "nullable": false,
+            "optional": true,
+        })
+    );
+}
+
+#[test]
+fn test_yaml_error_span() {
+    let template = jf::Template::parse("{a: %s,\n b: %%, c: %(c)q}").unwrap();
+
+    let err = template
+        .format(["x: y", "c=x"].map(Into::into))
+        .unwrap_err();
+    assert_eq!(err.returncode(), 3);
+    let span = err.span().unwrap();
+    assert_eq!((span.text.as_str(), span.line, span.column), ("%s", 1, 6));
+
+    let err = template.format(["1", "c=x"].map(Into::into)).unwrap_err();
+    let span = err.span().unwrap();
+    assert_eq!((span.text.as_str(), span.line, span.column), ("%", 2, 5));
+}
+
+#[test]
+fn test_yaml_error_value() {
+    let template = jf::Template::parse("{a: %s, b: %s, c: %(c)s}").unwrap();
+
+    let err = template
+        .format(["1", "2", "c=x: y"].map(Into::into))
+        .unwrap_err();
+    assert_eq!(err.returncode(), 3);
+    let jf::Error::Yaml(e) = &err else {
+        panic!("unexpected error: {err:?}");
+    };
+    assert_eq!(e.value, Some(jf::ValueSource::Arg(3)));
+    assert!(err.to_string().starts_with(
+        "yaml: value no. 3 substituted at '%(c)s' (column 22) produced invalid YAML: "
+    ));
+
+    let err = template
+        .format(["1", "x: y", "c=1"].map(Into::into))
+        .unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("yaml: value no. 2 substituted at '%s' (column 12)"));
+
+    let template = jf::Template::parse("[%*-s]").unwrap();
+    let options = jf::Options::default().stdin("1\0}".as_bytes());
+    let err = template.format_with([], options).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("yaml: stdin value no. 2 substituted at '%*-s' (column 4)"));
+
+    let err = jf::format(["{a: %%, b: 1}"].map(Into::into)).unwrap_err();
+    let jf::Error::Yaml(e) = &err else {
+        panic!("unexpected error: {err:?}");
+    };
+    assert_eq!(e.value, None);
+}
+
+#[test]
+fn test_diagnostic() {
+    let template = "{a: 1,\n b: %(b)q}";
+    let err = jf::format([template].map(Into::into)).unwrap_err();
+    assert_eq!(
+        jf::cli::diagnostic(&err, Some(template)),
+        "error: jf: no value for placeholder '%(b)q' at column 15
+ --> template:2:9
+  |
+2 |  b: %(b)q}
+  |     ^^^^^"
+    );
+
+    let err = jf::format(["%s", "1", "2"].map(Into::into)).unwrap_err();
+    assert_eq!(
+        jf::cli::diagnostic(&err, Some("%s")),
+        "error: jf: too many positional values, not enough positional placeholders"
+    );
+}
+
+#[test]
+fn test_error_json() {
+    let err = jf::format(["{foo: %(foo)q}"].map(Into::into)).unwrap_err();
+    let value: json::Value = json::from_str(&jf::cli::error_json(&err)).unwrap();
+    assert_eq!(
+        value,
+        json::json!({
+            "kind": "template",
+            "message": "jf: no value for placeholder '%(foo)q' at column 12",
+            "returncode": 1,
+            "line": 1,
+            "column": 13,
+            "offset": 12,
+            "placeholder": "%(foo)q",
+        })
+    );
+
+    let err = jf::format(["%s", "1", "2"].map(Into::into)).unwrap_err();
+    let value: json::Value = json::from_str(&jf::cli::error_json(&err)).unwrap();
+    assert_eq!(value["line"], json::Value::Null);
+    assert_eq!(value["offset"], json::Value::Null);
+    assert_eq!(value["placeholder"], json::Value::Null);
+
+    assert_eq!(
+        jf::cli::ErrorFormat::parse("json").unwrap(),
+        jf::cli::ErrorFormat::Json
+    );
+    assert_eq!(
+        jf::cli::ErrorFormat::parse("xml").unwrap_err().to_string(),
+        "jf: invalid error format 'xml', use `text` or `json`"
+    );
+
+    let err = jf::format(["{a: %%}"].map(Into::into)).unwrap_err();
+    assert_eq!((err.kind(), err.placeholder()), ("yaml", None));
+    assert_eq!(err.span().unwrap().line, 1);
+}
+
 #[test]
 fn test_yaml() {
     let args = ["{a: b, c: d, e: [f, g]}"].map(Into::into);
     assert_eq!(jf::format_yaml(args).unwrap(), "a: b\nc: d\ne:\n- f\n- g\n");
 }
 
+#[test]
+fn test_toml() {
+    let args = ["{a: b, c: [1, 2], d: {e: 1.5, f: [{g: true}]}}"].map(Into::into);
+    assert_eq!(
+        jf::format_toml(args).unwrap(),
+        "a = \"b\"\nc = [1, 2]\n\n[d]\ne = 1.5\n\n[[d.f]]\ng = true\n"
+    );
+}
+
+#[test]
+fn test_toml_error() {
+    for (template, error) in [
+        ("null", "cannot represent null at the top level"),
+        ("[1]", "the top-level value must be a table, found array"),
+        ("{a: {b: [1, null]}}", "cannot represent null at 'a.b[1]'"),
+        (
+            "{a: [1, x]}",
+            "cannot represent array with mixed types at 'a', found integer and string",
+        ),
+        ("{a: {1: x}}", "cannot represent non-string key 1 at 'a'"),
+        ("{a: !x y}", "cannot represent tagged value !x at 'a'"),
+        (
+            "{a: 18446744073709551615}",
+            "cannot represent integer 18446744073709551615 at 'a', it is out of range",
+        ),
+    ] {
+        let err = jf::format_toml([template].map(Into::into)).unwrap_err();
+        assert_eq!(err.to_string(), format!("toml: {error}"));
+        assert_eq!(err.returncode(), 5);
+    }
+}
+
+#[test]
+fn test_load_values() {
+    use jf::cli::ValuesFile;
+
+    let dir =
+        std::env::temp_dir().join(format!("jf-test-values-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let yaml = dir.join("values.yaml").to_string_lossy().to_string();
+    let json = dir.join("values.json").to_string_lossy().to_string();
+    let env = dir.join(".env").to_string_lossy().to_string();
+    std::fs::write(&yaml, "name: foo\ntags: [a, b]\nmeta: {k: 1}\nport: 80\n").unwrap();
+    std::fs::write(&json, r#"{"port": 90}"#).unwrap();
+    std::fs::write(&env, "# comment\nexport port=100\nuser='b a r'\n").unwrap();
+
+    let files = [
+        ValuesFile::Yaml(yaml.clone()),
+        ValuesFile::Yaml(json.clone()),
+    ];
+    let options = jf::Options::default()
+        .values(&jf::cli::load_values(&files).unwrap())
+        .unwrap();
+    let args = [
+        "{name: %(name)q, tags: [%(tags)*q], meta: {%(meta)**s}, port: %(port)d}",
+        "name=bar",
+    ];
+    assert_eq!(
+        jf::format_with(args.map(Into::into), options).unwrap(),
+        r#"{"name":"bar","tags":["a","b"],"meta":{"k":1},"port":90}"#
+    );
+
+    let files = [ValuesFile::Env(env.clone()), ValuesFile::Yaml(yaml.clone())];
+    let options = jf::Options::default()
+        .values(&jf::cli::load_values(&files).unwrap())
+        .unwrap();
+    let args = ["{user: %(user)q, port: %(port)q}", "user=baz"];
+    assert_eq!(
+        jf::format_with(args.map(Into::into), options).unwrap(),
+        r#"{"user":"baz","port":"80"}"#
+    );
+
+    std::fs::write(&yaml, "[a, b]").unwrap();
+    let err = jf::cli::load_values(&[ValuesFile::Yaml(yaml)]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "jf: named values must be a map of names to values"
+    );
+
+    std::fs::write(&json, r#"{"port": 90, "port": 91}"#).unwrap();
+    let err = jf::cli::load_values(&[ValuesFile::Yaml(json.clone())]).unwrap_err();
+    assert!(err.to_string().starts_with(&format!(
+        "jf: invalid values file '{json}': duplicate entry with key \"port\""
+    )));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_parse_dotenv() {
+    let text = r#"
+# comment
+export A=1
+B = two words # comment
+C="multi
+line \"quoted\"\n" # comment
+D='single # not a comment \n'
+E=
+F=a#b
+"#;
+    assert_eq!(
+        jf::cli::parse_dotenv(text).unwrap(),
+        [
+            ("A", "1"),
+            ("B", "two words"),
+            ("C", "multi\nline \"quoted\"\n"),
+            ("D", "single # not a comment \\n"),
+            ("E", ""),
+            ("F", "a#b"),
+        ]
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+    );
+
+    let err = jf::cli::parse_dotenv("A=1\nB\n").unwrap_err();
+    assert_eq!(err, "expected KEY=VALUE at line 2");
+    let err = jf::cli::parse_dotenv("A-B=1").unwrap_err();
+    assert_eq!(err, "invalid key 'A-B' at line 1");
+    let err = jf::cli::parse_dotenv("A=\"1\nB=2").unwrap_err();
+    assert_eq!(err, "unclosed quote at line 1");
+}
+
+#[test]
+fn test_each_records() {
+    use jf::cli::Each;
+
+    let records = |each: Each, input: &'static str| {
+        each.records(input.as_bytes())
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap()
+    };
+
+    assert_eq!(
+        records(Each::Lines, "a b\r\n\nc\n"),
+        [vec!["a b"], vec![""], vec!["c"]]
+    );
+    assert_eq!(records(Each::Nul, "a\nb\x00c\x00"), [["a\nb"], ["c"]]);
+    assert_eq!(
+        records(Each::Csv, "1,\"a, \"\"b\"\"\"\n2\n"),
+        [vec!["1", "a, \"b\""], vec!["2"]]
+    );
+
+    let template = jf::Template::parse("{n: %d, s: %q, t: %(t)q}").unwrap();
+    let output = records(Each::Csv, "1,a\n2,b\n")
+        .into_iter()
+        .map(|record| {
+            let args = record.into_iter().chain(["t=x".into()]).map(Into::into);
+            template.format_with(args, jf::Options::default().no_stdin())
+        })
+        .collect::<jf::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(
+        output,
+        [r#"{"n":1,"s":"a","t":"x"}"#, r#"{"n":2,"s":"b","t":"x"}"#]
+    );
+}
+
 #[test]
 fn test_pretty_json() {
     let args = ["{a: b, c: d, e: [f, g]}"].map(Into::into);
@@ -458,19 +1620,19 @@ fn test_invalid_placeholder_error() {
     let args = ["%z"].map(Into::into);
     assert_eq!(
         jf::format(args).unwrap_err().to_string(),
-        "jf: invalid placeholder '%z' at column 1, use one of '%s' or '%q', or escape it using '%%'"
+        "jf: invalid placeholder '%z' at column 1, use one of '%s', '%q', '%d', '%f', '%b' or '%j', or escape it using '%%'"
     );
 
     let args = ["%*z"].map(Into::into);
     assert_eq!(
         jf::format(args).unwrap_err().to_string(),
-        "jf: invalid placeholder '%*z' at column 2, use one of '%*s' or '%*q', or escape it using '%%'"
+        "jf: invalid placeholder '%*z' at column 2, use one of '%*s', '%*q', '%*d', '%*f', '%*b' or '%*j', or escape it using '%%'"
     );
 
     let args = ["%**z"].map(Into::into);
     assert_eq!(
         jf::format(args).unwrap_err().to_string(),
-        "jf: invalid placeholder '%**z' at column 3, use one of '%**s' or '%**q', or escape it using '%%'"
+        "jf: invalid placeholder '%**z' at column 3, use one of '%**s', '%**q', '%**d', '%**f', '%**b' or '%**j', or escape it using '%%'"
     );
 }
 
@@ -613,37 +1775,37 @@ fn test_invalid_named_placeholder_error() {
     let args = ["%(foo)x"].map(Into::into);
     assert_eq!(
         jf::format(args.clone()).unwrap_err().to_string(),
-        format!("jf: invalid named placeholder '%(foo)x' at column 6, use '%(foo)q' for quoted strings and '%(foo)s' for other values")
+        format!("jf: invalid named placeholder '%(foo)x' at column 6, use '%(foo)q' for quoted strings, '%(foo)d' for integers, '%(foo)f' for numbers, '%(foo)b' for booleans, '%(foo)j' for JSON values and '%(foo)s' for other values")
     );
 
     let args = ["%(foo)-"].map(Into::into);
     assert_eq!(
         jf::format(args.clone()).unwrap_err().to_string(),
-        format!("jf: invalid named placeholder '%(foo)-' at column 6, use '%(foo)q' for quoted strings and '%(foo)s' for other values")
+        format!("jf: invalid named placeholder '%(foo)-' at column 6, use '%(foo)q' for quoted strings, '%(foo)d' for integers, '%(foo)f' for numbers, '%(foo)b' for booleans, '%(foo)j' for JSON values and '%(foo)s' for other values")
     );
 
     let args = ["%(foo)*x"].map(Into::into);
     assert_eq!(
         jf::format(args.clone()).unwrap_err().to_string(),
-        format!("jf: invalid named placeholder '%(foo)*x' at column 7, use '%(foo)*q' for quoted strings and '%(foo)*s' for other values")
+        format!("jf: invalid named placeholder '%(foo)*x' at column 7, use '%(foo)*q' for quoted strings, '%(foo)*d' for integers, '%(foo)*f' for numbers, '%(foo)*b' for booleans, '%(foo)*j' for JSON values and '%(foo)*s' for other values")
     );
 
     let args = ["%(foo)*-"].map(Into::into);
     assert_eq!(
         jf::format(args.clone()).unwrap_err().to_string(),
-        format!("jf: invalid named placeholder '%(foo)*-' at column 7, use '%(foo)*q' for quoted strings and '%(foo)*s' for other values")
+        format!("jf: invalid named placeholder '%(foo)*-' at column 7, use '%(foo)*q' for quoted strings, '%(foo)*d' for integers, '%(foo)*f' for numbers, '%(foo)*b' for booleans, '%(foo)*j' for JSON values and '%(foo)*s' for other values")
     );
 
     let args = ["%(foo)**x"].map(Into::into);
     assert_eq!(
         jf::format(args.clone()).unwrap_err().to_string(),
-        format!("jf: invalid named placeholder '%(foo)**x' at column 8, use '%(foo)**q' for quoted strings and '%(foo)**s' for other values")
+        format!("jf: invalid named placeholder '%(foo)**x' at column 8, use '%(foo)**q' for quoted strings, '%(foo)**d' for integers, '%(foo)**f' for numbers, '%(foo)**b' for booleans, '%(foo)**j' for JSON values and '%(foo)**s' for other values")
     );
 
     let args = ["%(foo)**-"].map(Into::into);
     assert_eq!(
         jf::format(args.clone()).unwrap_err().to_string(),
-        format!("jf: invalid named placeholder '%(foo)**-' at column 8, use '%(foo)**q' for quoted strings and '%(foo)**s' for other values")
+        format!("jf: invalid named placeholder '%(foo)**-' at column 8, use '%(foo)**q' for quoted strings, '%(foo)**d' for integers, '%(foo)**f' for numbers, '%(foo)**b' for booleans, '%(foo)**j' for JSON values and '%(foo)**s' for other values")
     );
 }
 
@@ -695,6 +1857,17 @@ fn test_usage_example() {
         jf::format(args).unwrap(),
         r#"{"1":1,"two":"2","3":3,"four":"4","%":null}"#
     );
+
+    let args = [
+        "{port: %d, ratio: %f, debug: %(debug=false)b}",
+        "8080",
+        "0.5",
+    ]
+    .map(Into::into);
+    assert_eq!(
+        jf::format(args).unwrap(),
+        r#"{"port":8080,"ratio":0.5,"debug":false}"#
+    );
 }
 
 #[cfg(feature = "manpage")]